@@ -0,0 +1,280 @@
+use std::fs;
+use std::path::{Path, PathBuf};
+use std::sync::atomic::Ordering;
+use std::time::Duration;
+
+use crate::notation;
+use crate::rules::{self, BoardState, Move, Outcome, Player};
+use crate::search::{Limits, Search, MATE_BOUND, MAX_DEPTH};
+use crate::settings;
+use crate::tt;
+
+// Centipawns a move may give away before it is flagged
+const INACCURACY: i32 = 50;
+const MISTAKE: i32 = 100;
+const BLUNDER: i32 = 300;
+// Mates count as this many centipawns, so letting one slip is a blunder
+// while mating a move slower is not
+const MATE_SCORE: i32 = 1_500;
+// PGN export lines stay below this length
+const PGN_LINE: usize = 79;
+
+#[derive(Clone, Copy, PartialEq, Debug)]
+pub enum Judgement {
+    Inaccuracy,
+    Mistake,
+    Blunder,
+}
+
+impl Judgement {
+    fn from_loss(loss: i32) -> Option<Self> {
+        match loss {
+            l if l >= BLUNDER => Some(Judgement::Blunder),
+            l if l >= MISTAKE => Some(Judgement::Mistake),
+            l if l >= INACCURACY => Some(Judgement::Inaccuracy),
+            _ => None,
+        }
+    }
+
+    // Suffix after the move in the move list
+    pub fn symbol(&self) -> &'static str {
+        match self {
+            Judgement::Inaccuracy => "?!",
+            Judgement::Mistake => "?",
+            Judgement::Blunder => "??",
+        }
+    }
+
+    // Numeric annotation glyph for the same suffix in PGN
+    pub fn nag(&self) -> u8 {
+        match self {
+            Judgement::Inaccuracy => 6,
+            Judgement::Mistake => 2,
+            Judgement::Blunder => 4,
+        }
+    }
+
+    pub fn name(&self) -> &'static str {
+        match self {
+            Judgement::Inaccuracy => "Inaccuracy",
+            Judgement::Mistake => "Mistake",
+            Judgement::Blunder => "Blunder",
+        }
+    }
+}
+
+// What the engine thinks of one move of the game
+pub struct Annotation {
+    pub san: String,
+    pub player: Player,
+    pub fullmove: u16,
+    // Centipawns the move gave away against the engine's choice
+    pub loss: i32,
+    pub judgement: Option<Judgement>,
+    // The engine's move in SAN, kept for flagged moves only
+    pub best: Option<String>,
+}
+
+// Where the last analysed game is written
+pub fn pgn_path() -> Option<PathBuf> {
+    settings::project_dirs().map(|dirs| dirs.data_dir().join("analysis.pgn"))
+}
+
+// Score for the side to move, mates capped to `MATE_SCORE`, and the move
+// that gets it. No score when the search ended before its first iteration
+fn position_score(state: &BoardState, search: &mut Search, limits: &Limits) -> (Option<i32>, Option<Move>) {
+    match rules::outcome(state) {
+        Some(Outcome::Win(plyr)) if plyr == state.player => return (Some(MATE_SCORE), None),
+        Some(Outcome::Win(_)) => return (Some(-MATE_SCORE), None),
+        Some(Outcome::Draw) => return (Some(0), None),
+        None => (),
+    }
+
+    let mut score = None;
+    let best = search.go(state, limits, |info| score = Some(info.score));
+
+    let score = score.map(|s| match s {
+        s if s.abs() >= MATE_BOUND => s.signum() * MATE_SCORE,
+        s => s.clamp(-MATE_SCORE, MATE_SCORE),
+    });
+
+    (score, best)
+}
+
+// Search every position of the game for `per_move` and judge each move by
+// how much worse it scores than the engine's choice. `progress` hears the
+// number of the move being looked at; None when the search was stopped
+pub fn analyse(
+    start: &BoardState,
+    moves: &[Move],
+    search: &mut Search,
+    per_move: Duration,
+    mut progress: impl FnMut(usize),
+) -> Option<Vec<Annotation>> {
+    let stop = search.stop_flag();
+    // With a depth limit the search doesn't skip positions with a single
+    // move, those need a score as much as any other
+    let limits = Limits { depth: Some(MAX_DEPTH), movetime: Some(per_move), ..Limits::default() };
+
+    let mut state = start.clone();
+    let (mut score, mut best) = position_score(&state, search, &limits);
+    let mut annotations = Vec::with_capacity(moves.len());
+    let mut keys = Vec::with_capacity(moves.len());
+
+    for (idx, mv) in moves.iter().enumerate() {
+        progress(idx);
+
+        let mut next = state.clone();
+        next.make_move(*mv);
+        keys.push(tt::hash(&state));
+        search.set_history(keys.clone());
+        let (next_score, next_best) = position_score(&next, search, &limits);
+
+        if stop.load(Ordering::Relaxed) {
+            return None;
+        }
+
+        // The duck is moved by the side that just moved its piece
+        let after = match next.player == state.player {
+            true => next_score,
+            false => next_score.map(|s| -s),
+        };
+        let loss = match (best == Some(*mv), score, after) {
+            (false, Some(score), Some(after)) => (score - after).max(0),
+            _ => 0,
+        };
+        let judgement = Judgement::from_loss(loss);
+
+        annotations.push(Annotation {
+            san: notation::to_san(*mv, &state),
+            player: state.player,
+            fullmove: state.fullmove,
+            loss,
+            judgement,
+            best: best.filter(|_| judgement.is_some()).map(|b| notation::to_san(b, &state)),
+        });
+
+        state = next;
+        score = next_score;
+        best = next_best;
+    }
+
+    Some(annotations)
+}
+
+// PGN result of a finished game
+pub fn result(state: &BoardState) -> &'static str {
+    match (state.w_win, state.b_win, state.draw) {
+        (true, _, _) => "1-0",
+        (_, true, _) => "0-1",
+        (_, _, true) => "1/2-1/2",
+        _ => "*",
+    }
+}
+
+// The analysed game as PGN, flagged moves get a NAG and a comment naming
+// the engine's move
+pub fn to_pgn(start_fen: &str, state: &BoardState, names: (&str, &str), annotations: &[Annotation]) -> String {
+    let result = result(state);
+    let mut tags = vec![
+        ("Event", String::from("Casual game")),
+        ("Site", String::from("2-modded-chess")),
+        ("Date", String::from("????.??.??")),
+        ("Round", String::from("-")),
+        ("White", names.0.to_string()),
+        ("Black", names.1.to_string()),
+        ("Result", result.to_string()),
+    ];
+    if state.variant.name() != "Standard" {
+        tags.push(("Variant", state.variant.name()));
+    }
+    if start_fen != BoardState::new(state.variant).to_fen() || state.variant.chess960 {
+        tags.push(("SetUp", String::from("1")));
+        tags.push(("FEN", start_fen.to_string()));
+    }
+    tags.push(("Annotator", String::from("2-modded-chess")));
+
+    let mut pgn: String = tags.iter().map(|(tag, value)| format!("[{} \"{}\"]\n", tag, value)).collect();
+    pgn.push('\n');
+
+    // Black's moves need their number after a comment or at the start
+    let mut numbered = false;
+    let mut words = Vec::new();
+
+    for (idx, a) in annotations.iter().enumerate() {
+        let duck = idx > 0 && annotations[idx - 1].player == a.player;
+
+        match a.player {
+            Player::White if !duck => words.push(format!("{}.", a.fullmove)),
+            Player::Black if !duck && !numbered => words.push(format!("{}...", a.fullmove)),
+            _ => (),
+        }
+        words.push(a.san.clone());
+        numbered = true;
+
+        if let Some(judgement) = a.judgement {
+            words.push(format!("${}", judgement.nag()));
+            let best = a.best.as_ref().map_or(String::new(), |best| format!(" {} was best.", best));
+            let comment = format!("{{{}, loses {:.2}.{}}}", judgement.name(), a.loss as f32 / 100., best);
+            words.extend(comment.split(' ').map(String::from));
+            numbered = false;
+        }
+    }
+    words.push(result.to_string());
+
+    let mut line = String::new();
+    for word in words {
+        if !line.is_empty() && line.len() + 1 + word.len() > PGN_LINE {
+            pgn.push_str(&line);
+            pgn.push('\n');
+            line.clear();
+        }
+        if !line.is_empty() {
+            line.push(' ');
+        }
+        line.push_str(&word);
+    }
+    pgn.push_str(&line);
+    pgn.push('\n');
+
+    pgn
+}
+
+pub fn write_pgn(pgn: &str, path: &Path) -> Result<(), String> {
+    if let Some(dir) = path.parent() {
+        fs::create_dir_all(dir).map_err(|e| format!("{}: {}", dir.display(), e))?;
+    }
+
+    fs::write(path, pgn).map_err(|e| format!("{}: {}", path.display(), e))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::variant::Variant;
+
+    // A rook up, the check leaves black a single king move
+    #[test]
+    fn forced_reply_keeps_its_score() {
+        let variant = Variant::standard();
+        let start = BoardState::from_fen("7k/6p1/7p/8/8/8/5PPP/R5K1 w - - 0 1", variant).unwrap();
+
+        let mut state = start.clone();
+        let mut moves = Vec::new();
+        for text in ["a1a8", "h8h7"] {
+            let mv = Move::parse(text, state.height).and_then(|mv| rules::resolve_move(mv, &state)).unwrap();
+            if moves.len() == 1 {
+                assert_eq!(rules::legal_moves(&state), vec![mv]);
+            }
+            state.make_move(mv);
+            moves.push(mv);
+        }
+
+        let mut search = Search::new(16);
+        let annotations = analyse(&start, &moves, &mut search, Duration::from_millis(200), |_| ()).unwrap();
+
+        assert!(annotations[0].loss < INACCURACY, "check lost {}", annotations[0].loss);
+        assert_eq!(annotations[0].judgement, None);
+        assert_eq!(annotations[1].loss, 0);
+    }
+}
@@ -0,0 +1,86 @@
+use std::collections::HashMap;
+use std::fs;
+use std::path::Path;
+
+use ggez::{graphics::Image, Context, GameResult};
+
+use crate::rules::DUCK;
+use crate::variant;
+
+// Default piece set, compiled into the binary so it runs from anywhere
+const EMBEDDED_PIECES: [(char, &[u8]); 21] = [
+    ('K', include_bytes!("assets/K.png")),
+    ('Q', include_bytes!("assets/Q.png")),
+    ('R', include_bytes!("assets/R.png")),
+    ('B', include_bytes!("assets/B.png")),
+    ('N', include_bytes!("assets/N.png")),
+    ('P', include_bytes!("assets/P.png")),
+    ('k', include_bytes!("assets/k.png")),
+    ('q', include_bytes!("assets/q.png")),
+    ('r', include_bytes!("assets/r.png")),
+    ('b', include_bytes!("assets/b.png")),
+    ('n', include_bytes!("assets/n.png")),
+    ('p', include_bytes!("assets/p.png")),
+    // Fairy pieces
+    ('A', include_bytes!("assets/A.png")),
+    ('C', include_bytes!("assets/C.png")),
+    ('M', include_bytes!("assets/M.png")),
+    ('L', include_bytes!("assets/L.png")),
+    ('a', include_bytes!("assets/a.png")),
+    ('c', include_bytes!("assets/c.png")),
+    ('m', include_bytes!("assets/m.png")),
+    ('l', include_bytes!("assets/l.png")),
+    (DUCK, include_bytes!("assets/duck.png")),
+];
+
+// Load the piece images, preferring `<dir>/<piece>.png` (`duck.png` for the
+// duck) from a custom set and falling back to the embedded image for anything missing or unreadable
+pub fn load_pieces(ctx: &mut Context, dir: Option<&Path>) -> GameResult<HashMap<char, Image>> {
+    let mut pieces = HashMap::<char, Image>::new();
+
+    for (piece, bytes) in EMBEDDED_PIECES.iter() {
+        let custom = dir.and_then(|dir| load_custom(ctx, dir, *piece));
+
+        let img = match custom {
+            Some(img) => img,
+            None => Image::from_bytes(ctx, bytes)?,
+        };
+
+        pieces.insert(*piece, img);
+    }
+
+    // Fairy pieces of custom variants only come from a custom set, the board
+    // draws a lettered disc for any without an image
+    if let Some(dir) = dir {
+        for custom in variant::custom_variants() {
+            for (pc, _) in custom.pieces.iter() {
+                for piece in [*pc, pc.to_ascii_lowercase()].iter() {
+                    if pieces.contains_key(piece) {
+                        continue;
+                    }
+                    if let Some(img) = load_custom(ctx, dir, *piece) {
+                        pieces.insert(*piece, img);
+                    }
+                }
+            }
+        }
+    }
+
+    Ok(pieces)
+}
+
+fn load_custom(ctx: &mut Context, dir: &Path, piece: char) -> Option<Image> {
+    let path = match piece {
+        DUCK => dir.join("duck.png"),
+        _ => dir.join(format!("{}.png", piece)),
+    };
+    let bytes = fs::read(&path).ok()?;
+
+    match Image::from_bytes(ctx, &bytes) {
+        Ok(img) => Some(img),
+        Err(e) => {
+            eprintln!("Could not load {}: {}", path.display(), e);
+            None
+        }
+    }
+}
@@ -0,0 +1,63 @@
+use std::time::{Duration, Instant};
+
+use crate::rules::BoardState;
+use crate::search::{Limits, Search};
+use crate::tt::DEFAULT_HASH_MB;
+use crate::variant::Variant;
+
+// The usual perft test positions: the start, Kiwipete, a rook endgame,
+// two tactical middlegames and a quiet one
+const POSITIONS: [&str; 6] = [
+    "rnbqkbnr/pppppppp/8/8/8/8/PPPPPPPP/RNBQKBNR w KQkq - 0 1",
+    "r3k2r/p1ppqpb1/bn2pnp1/3PN3/1p2P3/2N2Q1p/PPPBBPPP/R3K2R w KQkq - 0 1",
+    "8/2p5/3p4/KP5r/1R3p1k/8/4P1P1/8 w - - 0 1",
+    "r3k2r/Pppp1ppp/1b3nbN/nP6/BBP1P3/q4N2/Pp1P2PP/R2Q1RK1 w kq - 0 1",
+    "rnbq1k1r/pp1Pbppp/2p5/8/2B5/8/PPP1NnPP/RNBQK2R w KQ - 1 8",
+    "r4rk1/1pp1qppp/p1np1n2/2b1p1B1/2B1P3/P1NP1N2/1PP1QPPP/R4RK1 w - - 0 10",
+];
+// Search time of each position
+const MOVETIME: Duration = Duration::from_secs(1);
+
+// Search every position with 1, 2, 4, ... up to `threads` threads and print
+// the speed of each, to see how well the search scales with more cores
+pub fn run(threads: usize) {
+    let variant = Variant::standard();
+    let limits = Limits { movetime: Some(MOVETIME), ..Limits::default() };
+
+    let mut counts: Vec<usize> = (0..).map(|n| 1 << n).take_while(|n| *n < threads).collect();
+    counts.push(threads);
+    let mut single = None;
+
+    for count in counts {
+        let mut search = Search::new(DEFAULT_HASH_MB);
+        search.set_threads(count);
+        let (mut nodes, mut elapsed) = (0, Duration::ZERO);
+
+        for fen in POSITIONS.iter() {
+            let state = match BoardState::from_fen(fen, variant) {
+                Ok(state) => state,
+                Err(e) => {
+                    eprintln!("Skipping bench position {}: {}", fen, e);
+                    continue;
+                }
+            };
+
+            search.clear();
+            let start = Instant::now();
+            search.go(&state, &limits, |_| ());
+            elapsed += start.elapsed();
+            nodes += search.nodes();
+        }
+
+        let nps = nodes * 1000 / elapsed.as_millis().max(1) as u64;
+        let single = *single.get_or_insert(nps.max(1));
+        println!(
+            "{:>3} threads: {:>10} nodes in {:.1} s, {:>8} nodes/s ({:.2}x)",
+            count,
+            nodes,
+            elapsed.as_secs_f32(),
+            nps,
+            nps as f32 / single as f32
+        );
+    }
+}
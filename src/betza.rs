@@ -0,0 +1,144 @@
+// Piece movement written in Betza notation, e.g. "N" for a knight, "BN" for
+// an archbishop or "fmWfcF" for a pawn without its double step
+
+#[derive(Clone, Copy, PartialEq, Debug)]
+pub enum Mode {
+    Any,
+    // Only onto an empty square
+    Move,
+    // Only onto an opponent's piece
+    Capture,
+}
+
+// One direction a piece can go in, as seen by white (negative dy is forward)
+#[derive(Clone, Copy, PartialEq, Debug)]
+pub struct Step {
+    pub dx: i8,
+    pub dy: i8,
+    // How often the step can be repeated in a line, 0 for no limit
+    pub range: u8,
+    pub mode: Mode,
+}
+
+// Leaps making up an atom, as (long, short) offsets, and the default range
+fn atom(letter: char) -> Option<(Vec<(i8, i8)>, u8)> {
+    let atom = match letter {
+        'W' => (vec![(1, 0)], 1),
+        'F' => (vec![(1, 1)], 1),
+        'D' => (vec![(2, 0)], 1),
+        'N' => (vec![(2, 1)], 1),
+        'A' => (vec![(2, 2)], 1),
+        'H' => (vec![(3, 0)], 1),
+        'C' => (vec![(3, 1)], 1),
+        'Z' => (vec![(3, 2)], 1),
+        'G' => (vec![(3, 3)], 1),
+        'K' => (vec![(1, 0), (1, 1)], 1),
+        'R' => (vec![(1, 0)], 0),
+        'B' => (vec![(1, 1)], 0),
+        'Q' => (vec![(1, 0), (1, 1)], 0),
+        _ => return None,
+    };
+
+    Some(atom)
+}
+
+// Every orientation of a (long, short) leap
+fn orientations(a: i8, b: i8) -> Vec<(i8, i8)> {
+    let mut dirs = Vec::with_capacity(8);
+
+    for (x, y) in [(a, b), (b, a)].iter() {
+        for (sx, sy) in [(1, 1), (1, -1), (-1, 1), (-1, -1)].iter() {
+            let dir = (x * sx, y * sy);
+            if !dirs.contains(&dir) {
+                dirs.push(dir);
+            }
+        }
+    }
+
+    dirs
+}
+
+// A direction group like "f" or "fr" accepts a step when every letter does
+fn in_direction(group: &str, dx: i8, dy: i8) -> bool {
+    group.chars().all(|c| match c {
+        'f' => dy < 0,
+        'b' => dy > 0,
+        'l' => dx < 0,
+        'r' => dx > 0,
+        'v' => dy.abs() > dx.abs(),
+        's' => dx.abs() > dy.abs(),
+        _ => false,
+    })
+}
+
+pub fn parse(betza: &str) -> Result<Vec<Step>, String> {
+    let mut steps = Vec::new();
+    let mut chars = betza.chars().peekable();
+    let mut mods = String::new();
+
+    while let Some(c) = chars.next() {
+        if c.is_ascii_lowercase() {
+            if !"mcfblrvs".contains(c) {
+                return Err(format!("unsupported modifier '{}' in \"{}\"", c, betza));
+            }
+            mods.push(c);
+            continue;
+        }
+
+        let (leaps, mut range) = atom(c).ok_or(format!("unknown atom '{}' in \"{}\"", c, betza))?;
+
+        // A doubled atom rides, a number limits the distance (0 for none)
+        if chars.peek() == Some(&c) {
+            chars.next();
+            range = 0;
+        } else {
+            let mut digits = String::new();
+            while let Some(d) = chars.peek().filter(|d| d.is_ascii_digit()) {
+                digits.push(*d);
+                chars.next();
+            }
+            if !digits.is_empty() {
+                range = digits.parse().map_err(|_| format!("bad range in \"{}\"", betza))?;
+            }
+        }
+
+        let mode = match (mods.contains('m'), mods.contains('c')) {
+            (true, false) => Mode::Move,
+            (false, true) => Mode::Capture,
+            _ => Mode::Any,
+        };
+
+        // "fr" is forward-right, "fb" is forward or backward
+        let mut groups: Vec<String> = Vec::new();
+        for d in mods.chars().filter(|d| !"mc".contains(*d)) {
+            let joins = match groups.last() {
+                Some(last) if last.len() == 1 => {
+                    let prev = last.chars().next().unwrap();
+                    ("fb".contains(prev) && "lr".contains(d)) || ("lr".contains(prev) && "fb".contains(d))
+                }
+                _ => false,
+            };
+
+            match joins {
+                true => groups.last_mut().unwrap().push(d),
+                false => groups.push(d.to_string()),
+            }
+        }
+
+        for (a, b) in leaps {
+            for (dx, dy) in orientations(a, b) {
+                if groups.is_empty() || groups.iter().any(|g| in_direction(g, dx, dy)) {
+                    steps.push(Step { dx, dy, range, mode });
+                }
+            }
+        }
+
+        mods.clear();
+    }
+
+    if !mods.is_empty() {
+        return Err(format!("modifiers without an atom in \"{}\"", betza));
+    }
+
+    Ok(steps)
+}
@@ -0,0 +1,302 @@
+use std::fs;
+use std::path::Path;
+use std::sync::OnceLock;
+
+use serde::Deserialize;
+
+use crate::betza::{Mode, Step};
+use crate::pieces;
+use crate::rules::{self, BoardState, Player, Point, POCKET_PIECES};
+use crate::variant::Kind;
+
+// Tunable weights of the variant terms, read from `eval.toml`
+#[derive(Clone, Deserialize, Debug)]
+#[serde(default)]
+pub struct Weights {
+    // King of the hill: bonus for each king step less to the hill
+    pub hill_step: i32,
+    // Taken off when every way to the hill crosses an attacked square
+    pub hill_unsafe_path: i32,
+    // Three-check: bonus by checks still needed, one check left first
+    pub checks_left: Vec<i32>,
+    // Bonus for each move that would give check
+    pub checking_move: i32,
+    // Taken off for each square around the king the opponent attacks
+    pub king_exposure: i32,
+}
+
+impl Default for Weights {
+    fn default() -> Self {
+        Self {
+            hill_step: 40,
+            hill_unsafe_path: 60,
+            checks_left: vec![700, 300, 120],
+            checking_move: 15,
+            king_exposure: 20,
+        }
+    }
+}
+
+static WEIGHTS: OnceLock<Weights> = OnceLock::new();
+
+pub fn weights() -> &'static Weights {
+    WEIGHTS.get_or_init(Weights::default)
+}
+
+// Read the weights file once at startup, anything missing keeps its default
+pub fn load_weights(path: &Path) {
+    let weights = match fs::read_to_string(path) {
+        Ok(text) => match toml::from_str(&text) {
+            Ok(weights) => weights,
+            Err(e) => {
+                eprintln!("Ignoring invalid evaluation weights in {}: {}", path.display(), e);
+                Weights::default()
+            }
+        },
+        Err(_) => Weights::default(),
+    };
+
+    let _ = WEIGHTS.set(weights);
+}
+
+// Centipawn value of a piece of either colour, kings are never traded so
+// they count for nothing
+pub fn piece_value(pc: char, state: &BoardState) -> i32 {
+    if let Some(steps) = state.variant.custom().and_then(|c| c.piece(pc)) {
+        return custom_value(steps);
+    }
+
+    match pc.to_ascii_uppercase() {
+        'P' => 100,
+        'N' => 320,
+        'B' => 330,
+        'R' => 500,
+        'Q' => 900,
+        'A' => 850,
+        'C' => 900,
+        'M' => 1250,
+        'L' => 250,
+        'K' => match state.variant.kind {
+            Kind::Antichess => 300,
+            _ => 0,
+        },
+        pc => pieces::steps(pc).map_or(0, custom_value),
+    }
+}
+
+// Rough worth of a piece nobody has rated, from how many ways it can go:
+// a knight comes out near its usual value
+fn custom_value(steps: &[Step]) -> i32 {
+    steps
+        .iter()
+        .map(|step| {
+            let value = match step.range {
+                1 => 40,
+                _ => 110,
+            };
+            match step.mode {
+                Mode::Any => value,
+                _ => value / 2,
+            }
+        })
+        .sum()
+}
+
+// Small bonus for standing near the middle of the board
+fn centrality(x: u8, y: u8, state: &BoardState) -> i32 {
+    let dx = (2 * x as i32 + 1 - state.width as i32).abs();
+    let dy = (2 * y as i32 + 1 - state.height as i32).abs();
+
+    (state.width as i32 + state.height as i32 - dx - dy) * 2
+}
+
+// Bonus per rank a pawn on file `x` has advanced
+fn pawn_advance(x: u8, state: &BoardState) -> i32 {
+    4 + state.width as i32 - (2 * x as i32 + 1 - state.width as i32).abs()
+}
+
+// Score of the position in centipawns for the side to move
+pub fn evaluate(state: &BoardState) -> i32 {
+    let mut score = 0;
+
+    for (x, y) in rules::squares(state) {
+        let ch = state.board[y as usize][x as usize];
+        if !rules::is_piece(ch) || ch == rules::DUCK {
+            continue;
+        }
+
+        let mut value = piece_value(ch, state);
+
+        value += match ch {
+            // Pawns are worth more the closer they get to promoting, the
+            // centre ones most
+            'P' => (state.height - 1 - y) as i32 * pawn_advance(x, state),
+            'p' => y as i32 * pawn_advance(x, state),
+            // Racing kings is all about the king's rank
+            'K' | 'k' if state.variant.kind == Kind::RacingKings => (state.height - 1 - y) as i32 * 80,
+            'K' | 'k' => 0,
+            _ => centrality(x, y, state),
+        };
+
+        match rules::is_white_piece(ch) {
+            true => score += value,
+            false => score -= value,
+        }
+    }
+
+    for (side, pocket) in state.pockets.iter().enumerate() {
+        let value: i32 = POCKET_PIECES
+            .iter()
+            .zip(pocket.iter())
+            .map(|(pc, n)| piece_value(*pc, state) * *n as i32)
+            .sum();
+
+        match side {
+            0 => score += value,
+            _ => score -= value,
+        }
+    }
+
+    score += variant_terms(state, weights());
+
+    // Losing pieces is the goal in Antichess
+    if state.variant.kind == Kind::Antichess {
+        score = -score;
+    }
+
+    match state.player {
+        Player::White => score,
+        Player::Black => -score,
+    }
+}
+
+// King of the hill and N-check terms, for white
+fn variant_terms(state: &BoardState, weights: &Weights) -> i32 {
+    let goals = match state.variant.kind {
+        // Racing Kings scores the king's rank instead
+        Kind::RacingKings => Vec::new(),
+        _ => rules::goal_squares(state),
+    };
+    let checks = rules::checks_to_win(state.variant);
+
+    if goals.is_empty() && checks.is_none() {
+        return 0;
+    }
+
+    let mut score = 0;
+
+    for plyr in [Player::White, Player::Black].iter() {
+        let king = match state.king(*plyr) {
+            Some(king) => king,
+            None => continue,
+        };
+        let attacked = rules::attacked_squares(plyr.switch(), state);
+        let mut value = 0;
+
+        if !goals.is_empty() {
+            let (steps, safe) = hill_distance(*plyr, king, &goals, attacked, state);
+            value += weights.hill_step * (state.width.max(state.height) as i32 - steps);
+            if !safe {
+                value -= weights.hill_unsafe_path;
+            }
+        }
+
+        if let Some(needed) = checks {
+            let given = match plyr {
+                Player::White => state.w_checks,
+                Player::Black => state.b_checks,
+            };
+            let left = needed.saturating_sub(given).max(1) as usize;
+            value += weights.checks_left.get(left - 1).copied().unwrap_or(0);
+
+            if let Some(other) = state.king(plyr.switch()) {
+                value += weights.checking_move * checking_moves(*plyr, other, state);
+            }
+
+            let exposed = king_zone(king, state).filter(|sq| attacked & rules::square_bit(*sq) != 0).count();
+            value -= weights.king_exposure * exposed as i32;
+        }
+
+        match plyr {
+            Player::White => score += value,
+            Player::Black => score -= value,
+        }
+    }
+
+    score
+}
+
+// The king's square and its neighbours
+fn king_zone(king: (u8, u8), state: &BoardState) -> impl Iterator<Item = (u8, u8)> {
+    let (width, height) = (state.width as i8, state.height as i8);
+
+    (-1..=1i8)
+        .flat_map(move |dy| (-1..=1i8).map(move |dx| (king.0 as i8 + dx, king.1 as i8 + dy)))
+        .filter(move |(x, y)| (0..width).contains(x) && (0..height).contains(y))
+        .map(|(x, y)| (x as u8, y as u8))
+}
+
+// King steps to the nearest goal square without standing on an attacked
+// square on the way, or the straight distance and false when there is no
+// such way
+fn hill_distance(plyr: Player, king: (u8, u8), goals: &[(u8, u8)], attacked: u128, state: &BoardState) -> (i32, bool) {
+    let mut seen = rules::square_bit(king);
+    let mut frontier = vec![king];
+    let mut steps = 0;
+
+    while !frontier.is_empty() {
+        if frontier.iter().any(|sq| goals.contains(sq)) {
+            return (steps, true);
+        }
+
+        let mut next = Vec::new();
+        for sq in frontier.iter() {
+            for to in king_zone(*sq, state) {
+                let ch = state.board[to.1 as usize][to.0 as usize];
+                let bit = rules::square_bit(to);
+                // The king can take an opponent's piece on the way, not stand on its own
+                let free = !rules::is_piece(ch) || rules::is_opponent(plyr, ch);
+
+                if seen & bit == 0 && attacked & bit == 0 && free {
+                    seen |= bit;
+                    next.push(to);
+                }
+            }
+        }
+
+        frontier = next;
+        steps += 1;
+    }
+
+    let straight = goals
+        .iter()
+        .map(|(x, y)| (*x as i32 - king.0 as i32).abs().max((*y as i32 - king.1 as i32).abs()))
+        .min()
+        .unwrap_or(0);
+
+    (straight, false)
+}
+
+// Moves of `plyr` that would check the king on `king`, discovered checks
+// left out
+fn checking_moves(plyr: Player, king: (u8, u8), state: &BoardState) -> i32 {
+    let mut mover = state.clone();
+    mover.player = plyr;
+    let target = rules::square_bit(king);
+    let mut count = 0;
+
+    for (x, y) in rules::squares(state) {
+        let ch = state.board[y as usize][x as usize];
+        if !rules::is_piece(ch) || ch == rules::DUCK || rules::is_opponent(plyr, ch) || ch.to_ascii_uppercase() == 'K' {
+            continue;
+        }
+
+        for to in rules::get_piece_moves(ch, Point::new(x, y), &mover) {
+            if rules::attack_map(ch, to, Some((x, y)), state) & target != 0 {
+                count += 1;
+            }
+        }
+    }
+
+    count
+}
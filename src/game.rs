@@ -1,777 +1,1350 @@
 use std::collections::HashMap;
-use std::fmt;
+use std::fs;
+use std::path::Path;
+use std::sync::atomic::{AtomicBool, AtomicUsize, Ordering};
+use std::sync::mpsc::{self, Receiver, TryRecvError};
+use std::sync::Arc;
+use std::thread;
+use std::time::Duration;
 
 use ggez::{
-    event::{EventHandler, MouseButton},
+    event::{self, EventHandler, KeyCode, KeyMods, MouseButton},
     graphics::{self, Color, DrawMode, DrawParam, Image},
-    Context, GameResult,
+    timer, Context, GameResult,
 };
 
 use std::vec::Vec;
 
-const SQUARE_SIZE: i32 = 100;
-
+use rand::Rng;
+
+use crate::analysis::{self, Annotation};
+use crate::assets;
+use crate::book::Book;
+use crate::notation;
+use crate::rules::{self, BoardState, Move, Outcome, Player};
+use crate::save::SavedGame;
+use crate::search::{self, Clock, Info, Limits, Search, MATE, MATE_BOUND};
+use crate::settings::{Settings, TimeControl};
+use crate::skill;
+use crate::theme::Theme;
+use crate::variant::{Kind, Variant};
+
+const PANEL_WIDTH: f32 = 200.;
+const INPUT_HEIGHT: f32 = 40.;
+// How long an Atomic explosion stays on the board
+const EXPLOSION_TIME: Duration = Duration::from_millis(500);
+// Quiet time after the last resize event before the window size is saved
+const RESIZE_SETTLE: Duration = Duration::from_millis(500);
+// Size of a Crazyhouse pocket piece in the side panel
+const POCKET_SLOT: f32 = 36.;
+// Thinking time of the computer in games without a clock
+const COMPUTER_MOVETIME: Duration = Duration::from_secs(1);
+// Search time behind a hint and behind the evaluation bar
+const HINT_MOVETIME: Duration = Duration::from_secs(1);
+const ANALYSIS_MOVETIME: Duration = Duration::from_millis(500);
+const EVAL_BAR_WIDTH: f32 = 8.;
+// Search time for each position of a game under analysis
+const REVIEW_MOVETIME: Duration = Duration::from_millis(300);
+// Width of a column of the analysed move list
+const MOVE_LIST_COLUMN: f32 = 330.;
+const MOVE_LIST_LINE: f32 = 20.;
+
+// What the background search is for
+#[derive(Clone, Copy, PartialEq)]
+enum Job {
+    // The computer's move
+    Move,
+    // The move to highlight for the player
+    Hint,
+    // Just the score, for the evaluation bar
+    Analysis,
+}
 
-#[derive(Clone, Copy)]
-enum Player {
-    White,
-    Black,
+// Score of the position for white as shown next to the board, mates and
+// hill races in moves, negative when black gets there
+#[derive(Clone, Copy, PartialEq)]
+enum Evaluation {
+    Centipawns(i32),
+    Mate(i32),
+    Hill(i32),
+    // DTZ from the tablebases, 0 for a draw
+    Tablebase(i32),
 }
 
-impl Player {
-    fn switch(&self) -> Self {
-        match *self {
-            Self::White => Self::Black,
-            Self::Black => Self::White,
+impl Evaluation {
+    // Score from the last iteration of a search from `state`
+    fn from_info(info: &Info, state: &BoardState) -> Self {
+        let white = match state.player {
+            Player::White => 1,
+            Player::Black => -1,
+        };
+
+        if let Some(dtz) = info.tablebase {
+            return Evaluation::Tablebase(dtz * white);
         }
-    }
-}
 
-struct Point<T>
-where
-    T: Copy,
-{
-    x: T,
-    y: T,
-}
+        if info.score.abs() < MATE_BOUND {
+            return Evaluation::Centipawns(info.score * white);
+        }
 
-impl<T> Point<T>
-where
-    T: Copy,
-{
-    fn new(x: T, y: T) -> Self {
-        Self { x, y }
+        let moves = (MATE - info.score.abs() + 1) / 2 * info.score.signum() * white;
+
+        // Follow the line to see whether it ends with a king on the hill
+        let mut end = state.clone();
+        for mv in info.pv.iter() {
+            end.make_move(*mv);
+        }
+        let on_goal = rules::goal_squares(&end).iter().any(|(x, y)| {
+            let ch = end.board[*y as usize][*x as usize];
+            ch == 'K' || ch == 'k'
+        });
+
+        match on_goal {
+            true => Evaluation::Hill(moves),
+            false => Evaluation::Mate(moves),
+        }
     }
-}
 
-impl<T> std::clone::Clone for Point<T>
-where
-    T: Copy,
-{
-    fn clone(&self) -> Self {
-        Self {
-            x: self.x,
-            y: self.y,
+    // Share of the bar that is white, from the bottom
+    fn white_share(&self) -> f32 {
+        match *self {
+            Evaluation::Centipawns(cp) => (0.5 + cp as f32 / 2000.).clamp(0.05, 0.95),
+            Evaluation::Mate(n) | Evaluation::Hill(n) => match n > 0 {
+                true => 1.,
+                false => 0.,
+            },
+            Evaluation::Tablebase(dtz) => match dtz.signum() {
+                1 => 1.,
+                -1 => 0.,
+                _ => 0.5,
+            },
         }
     }
-}
 
-struct BoardState {
-    board: [[char; 8]; 8],
-    player: Player,
-    wk_pos: (u8, u8),
-    bk_pos: (u8, u8),
-    enp_b: u8,
-    enp_w: u8,
-    castling: u8,
-    b_check: bool,
-    w_check: bool,
-    b_checks: u8,
-    w_checks: u8,
-    w_win: bool,
-    b_win: bool,
-}
+    fn label(&self) -> String {
+        let side = |n: i32| if n > 0 { "White" } else { "Black" };
 
-impl std::clone::Clone for BoardState {
-    fn clone(&self) -> Self {
-        Self {
-            board: self.board.clone(),
-            player: self.player,
-            wk_pos: self.wk_pos,
-            bk_pos: self.bk_pos,
-            enp_b: self.enp_b,
-            enp_w: self.enp_w,
-            castling: self.castling,
-            b_check: self.b_check,
-            w_check: self.w_check,
-            b_checks: self.b_checks,
-            w_checks: self.w_checks,
-            w_win: self.w_win,
-            b_win: self.b_win,
+        match *self {
+            Evaluation::Centipawns(cp) => format!("{:+.2}", cp as f32 / 100.),
+            Evaluation::Mate(n) => format!("{} mates in {}", side(n), n.abs()),
+            Evaluation::Hill(n) => format!("{} king reaches\nthe hill in {}", side(n), n.abs()),
+            Evaluation::Tablebase(0) => String::from("Tablebase draw"),
+            Evaluation::Tablebase(n) => format!("{} wins\n(tablebase, DTZ {})", side(n), n.abs()),
         }
     }
 }
 
-impl fmt::Display for BoardState {
-    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
-        let mut out = String::from("[\n");
-
-        for row in self.board.iter() {
-            out.push_str(&format!("\t{:?}\n", row));
-        }
+// The computer's search running on a worker thread, which hands the
+// searcher back together with its move
+struct Thinking {
+    job: Job,
+    stop: Arc<AtomicBool>,
+    reply: Receiver<(Search, Option<Move>, Option<Info>)>,
+    // Undo, resign or a new game made the answer useless
+    cancelled: bool,
+}
 
-        out.push_str("]");
+// Analysis of the finished game on a worker thread, the searcher comes back
+// with the annotated moves, None when stopped
+struct Review {
+    stop: Arc<AtomicBool>,
+    // Number of the move being analysed
+    progress: Arc<AtomicUsize>,
+    reply: Receiver<(Search, Option<Vec<Annotation>>)>,
+}
 
-        write!(f, "{}", out)
-    }
+// Searcher with the table size and threads from the settings
+fn new_search(settings: &Settings) -> Search {
+    let mut search = Search::new(settings.hash_mb);
+    search.set_threads(settings.threads);
+    search
 }
 
 pub struct RChess {
-    board: [[Color; 8]; 8],
-    board_pcs: [[char; 8]; 8],
+    board: [[Color; rules::MAX_SIZE]; rules::MAX_SIZE],
+    state: BoardState,
+    start_fen: String,
+    // Scharnagl number of the Chess960 starting position
+    chess960_index: Option<u16>,
+    history: Vec<Move>,
     current: Option<char>,
     current_pos: Option<(u8, u8)>,
     moves: Vec<(u8, u8)>,
     pieces: HashMap<char, Image>,
-    turn: Player,
-    w_color: Color,
-    b_color: Color,
+    settings: Settings,
+    themes: Vec<Theme>,
+    theme_idx: usize,
     sq_size: i32,
     moving: bool,
     needs_draw: bool,
-    enp_b: u8,
-    enp_w: u8,
-    w_king_pos: (u8, u8),
-    b_king_pos: (u8, u8),
-    castling: u8,
-    w_check: bool,
-    b_check: bool,
-    b_checks: u8,
-    w_checks: u8,
-    w_win: bool,
-    b_win: bool,
+    time_control: Option<TimeControl>,
+    w_time: Duration,
+    b_time: Duration,
+    clock_running: bool,
+    // Unfinished game found on startup, waiting for the player to pick it up
+    resume: Option<SavedGame>,
+    // New game menu picking the variant, clock and opponent
+    menu: bool,
+    message: Option<String>,
+    last_move: Option<String>,
+    // Move typed on the keyboard, submitted with Enter
+    input: String,
+    // Square picked with the arrow keys
+    cursor: Option<(u8, u8)>,
+    // A key press was already handled, drop the character it produces
+    skip_text: bool,
+    // Atomic capture square and how long the explosion is still shown
+    explosion: Option<((u8, u8), Duration)>,
+    // Time left until a new window size is saved, None once it is
+    resize_save: Option<Duration>,
+    // Away on the worker thread while the computer thinks
+    search: Option<Search>,
+    thinking: Option<Thinking>,
+    // Best move shown on the board after asking for a hint
+    hint: Option<Move>,
+    hint_wanted: bool,
+    // Latest score for the evaluation bar, None until the position is analysed
+    evaluation: Option<Evaluation>,
+    review: Option<Review>,
+    review_wanted: bool,
+    // Moves of the analysed game for the move list
+    annotations: Vec<Annotation>,
+    // Opening book of the variant being played
+    book: Option<Book>,
 }
 
 impl RChess {
     // Create a new instance of RChess
-    pub fn new(ctx: &mut Context) -> GameResult<Self> {
-        let mut pieces = HashMap::<char, Image>::new();
-
-        let board_pcs = [
-            ['r', 'n', 'b', 'q', 'k', 'b', 'n', 'r'],
-            ['p', 'p', 'p', 'p', 'p', 'p', 'p', 'p'],
-            ['-', '-', '-', '-', '-', '-', '-', '-'],
-            ['-', '-', '-', '-', '-', '-', '-', '-'],
-            ['-', '-', '-', '-', '-', '-', '-', '-'],
-            ['-', '-', '-', '-', '-', '-', '-', '-'],
-            ['P', 'P', 'P', 'P', 'P', 'P', 'P', 'P'],
-            ['R', 'N', 'B', 'Q', 'K', 'B', 'N', 'R'],
-        ];
-
-        for row in board_pcs.iter() {
-            for piece in row.iter() {
-                if pieces.contains_key(piece) {
-                    continue;
-                }
-
-                if piece != &'-' {
-                    let img = Image::new(ctx, &format!("/{}.png", piece))?;
-                    pieces.insert(*piece, img);
-                }
+    pub fn new(ctx: &mut Context, settings: Settings) -> GameResult<Self> {
+        let pieces = assets::load_pieces(ctx, settings.pieces.as_deref())?;
+
+        let theme = Theme::load(&settings.theme).unwrap_or_else(|e| {
+            eprintln!("Could not load theme: {}", e);
+            Theme::default()
+        });
+
+        let mut themes = Theme::builtin();
+        let theme_idx = match themes.iter().position(|t| t.name == theme.name) {
+            Some(idx) => idx,
+            None => {
+                themes.push(theme);
+                themes.len() - 1
             }
-        }
+        };
+
+        let resume = SavedGame::autosave_path()
+            .and_then(|path| SavedGame::load(&path).ok())
+            .filter(|saved| !saved.moves.is_empty());
 
-        let w_color = Color::from_rgb(222, 222, 222);
-        let b_color = Color::from_rgb(40, 40, 40);
+        let state = BoardState::new(settings.variant);
+        let sq_size = Self::square_size(settings.window_width, settings.window_height, &state);
+        let search = new_search(&settings);
 
         let mut chess = Self {
-            board: [[w_color.clone(); 8]; 8],
-            board_pcs,
+            board: [[themes[theme_idx].light; rules::MAX_SIZE]; rules::MAX_SIZE],
+            start_fen: state.to_fen(),
+            chess960_index: None,
+            state,
+            history: Vec::new(),
             current: None,
             current_pos: None,
             moves: Vec::new(),
-            pieces: pieces,
-            turn: Player::White,
-            w_color,
-            b_color,
-            sq_size: SQUARE_SIZE,
+            pieces,
+            sq_size,
+            time_control: settings.clock,
+            settings,
+            themes,
+            theme_idx,
             moving: false,
             needs_draw: true,
-            enp_b: 0,
-            enp_w: 0,
-            w_king_pos: (4, 7),
-            b_king_pos: (4, 0),
-            castling: 0b1111,
-            w_check: false,
-            b_check: false,
-            b_checks: 0,
-            w_checks: 0,
-            w_win: false,
-            b_win: false,
+            w_time: Duration::ZERO,
+            b_time: Duration::ZERO,
+            clock_running: false,
+            menu: resume.is_none(),
+            resume,
+            message: None,
+            last_move: None,
+            input: String::new(),
+            cursor: None,
+            skip_text: false,
+            explosion: None,
+            resize_save: None,
+            search: Some(search),
+            thinking: None,
+            hint: None,
+            hint_wanted: false,
+            evaluation: None,
+            review: None,
+            review_wanted: false,
+            annotations: Vec::new(),
+            book: None,
         };
 
-        chess.reset_board();
+        chess.new_game();
 
         Ok(chess)
     }
-    
-    fn reset_board(&mut self) {
-        for y in 0..8 {
-            let row_even = y % 2 == 0;
 
-            for x in 0..8 {
-                let col_even = x % 2 == 0;
+    // Set up the starting position using the variant and clock from the settings,
+    // a random one out of the 960 when playing Chess960
+    fn new_game(&mut self) {
+        let shuffle = rules::start_fen(self.settings.variant) == rules::START_FEN;
+        let index = match self.settings.variant.chess960 && shuffle {
+            true => Some(rand::thread_rng().gen_range(0..960)),
+            false => None,
+        };
 
-                self.board[y][x] = if (col_even && row_even) || (!col_even && !row_even) {
-                    self.w_color.clone()
-                } else {
-                    self.b_color.clone()
-                }
-            }
-        }
-        let center_w_color = Color::from_rgb(255, 215, 0);
-        let center_b_color = Color::from_rgb(185, 145, 0);
-        self.board[3][4] = center_b_color;
-        self.board[4][3] = center_b_color;
-        self.board[3][3] = center_w_color;
-        self.board[4][4] = center_w_color;
+        self.start_position(index);
+    }
+
+    // Chess960 position from the command line, a random one for None. Only
+    // this game is Chess960, the settings keep their variant
+    pub fn start_chess960(&mut self, index: Option<u16>) {
+        let index = index.unwrap_or_else(|| rand::thread_rng().gen_range(0..960));
+        self.start_position(Some(index));
     }
 
-    fn get_board_state(&self) -> BoardState {
-        BoardState {
-            board: self.board_pcs.clone(),
-            player: self.turn,
-            wk_pos: self.w_king_pos,
-            bk_pos: self.b_king_pos,
-            castling: self.castling,
-            enp_b: self.enp_b,
-            enp_w: self.enp_w,
-            w_check: self.w_check,
-            b_check: self.b_check,
-            b_checks: self.b_checks,
-            w_checks: self.w_checks,
-            w_win: self.w_win,
-            b_win: self.b_win,
+    // Start a game from the given Chess960 position, or the normal one for None
+    fn start_position(&mut self, index: Option<u16>) {
+        self.state = match index {
+            Some(index) => BoardState::chess960(index, Variant { chess960: true, ..self.settings.variant }),
+            None => BoardState::new(self.settings.variant),
+        };
+        self.chess960_index = index;
+        self.book = Book::for_variant(self.state.variant);
+        self.explosion = None;
+        self.position_changed();
+        if let Some(search) = &mut self.search {
+            search.clear();
         }
+        self.start_fen = self.state.to_fen();
+        self.history.clear();
+
+        self.current = None;
+        self.current_pos = None;
+        self.moves.clear();
+        self.moving = false;
+        self.message = None;
+        self.last_move = None;
+        self.input.clear();
+
+        self.time_control = self.settings.clock;
+        let base = self.time_control.map_or(0, |tc| tc.base_secs);
+        self.w_time = Duration::from_secs(base as u64);
+        self.b_time = Duration::from_secs(base as u64);
+        self.clock_running = false;
+
+        self.reset_board();
+        self.needs_draw = true;
     }
 
-    fn is_white_piece(pc: char) -> bool {
-        ['K', 'Q', 'R', 'N', 'B', 'P'].contains(&pc)
+    // Fit the board, the side panel and the move input into the window
+    fn square_size(width: f32, height: f32, state: &BoardState) -> i32 {
+        let files = state.width as f32;
+        let ranks = state.height as f32;
+
+        ((width - PANEL_WIDTH) / files).min((height - INPUT_HEIGHT) / ranks).max(16.) as i32
     }
 
-    fn is_black_piece(pc: char) -> bool {
-        ['k', 'q', 'r', 'n', 'b', 'p'].contains(&pc)
+    // Width and height of the board on screen
+    fn board_px(&self) -> (f32, f32) {
+        let sq = self.sq_size as f32;
+
+        (self.state.width as f32 * sq, self.state.height as f32 * sq)
     }
 
-    fn is_opponent(plyr: Player, ch: char) -> bool {
-        match plyr {
-            Player::White => Self::is_black_piece(ch),
-            Player::Black => Self::is_white_piece(ch),
+    // Heights of the black and white info in the side panel, black's sits on
+    // top unless the board is flipped
+    fn panel_rows(&self) -> (f32, f32) {
+        let board_px = self.board_px().1.max(8. * self.sq_size as f32);
+
+        if self.settings.flipped {
+            (board_px * 0.625, board_px * 0.375)
+        } else {
+            (board_px * 0.375, board_px * 0.625)
         }
     }
 
-    fn is_piece(ch: char) -> bool {
-        ch != '-'
+    // Where a Crazyhouse pocket piece is drawn, beyond the player's clock
+    fn pocket_rect(&self, plyr: Player, idx: usize) -> graphics::Rect {
+        let (b_y, w_y) = self.panel_rows();
+        let row_y = match plyr {
+            Player::White => w_y,
+            Player::Black => b_y,
+        };
+
+        let y = if row_y > b_y.min(w_y) { row_y + 90. } else { row_y - 90. - POCKET_SLOT };
+        let x = self.board_px().0 + 10. + idx as f32 * POCKET_SLOT;
+
+        graphics::Rect::new(x, y, POCKET_SLOT, POCKET_SLOT)
     }
-    
-    fn white_won(&mut self) -> () {
-        self.w_win = true;
+
+    // Pocket piece of the side to move under the mouse
+    fn pocket_at(&self, x: f32, y: f32) -> Option<char> {
+        if self.state.variant.kind != Kind::Crazyhouse {
+            return None;
+        }
+
+        let side = match self.state.player {
+            Player::White => 0,
+            Player::Black => 1,
+        };
+
+        (0..rules::POCKET_PIECES.len())
+            .find(|idx| self.state.pockets[side][*idx] > 0 && self.pocket_rect(self.state.player, *idx).contains([x, y]))
+            .map(|idx| rules::POCKET_PIECES[idx])
     }
 
-    fn black_won(&mut self) {
-        self.b_win = true;
+    // Board coordinates of the square drawn at the given column/row on screen
+    fn orient(&self, x: usize, y: usize) -> (usize, usize) {
+        if self.settings.flipped {
+            (self.state.width as usize - 1 - x, self.state.height as usize - 1 - y)
+        } else {
+            (x, y)
+        }
     }
 
-    fn move_piece_to(from: Point<u8>, to: Point<u8>, state: &mut BoardState) {
-        let x = from.x as usize;
-        let y = from.y as usize;
+    fn theme(&self) -> &Theme {
+        &self.themes[self.theme_idx]
+    }
 
-        let ch = state.board[y][x];
-        state.enp_b = 0;
-        state.enp_w = 0;
+    fn reset_board(&mut self) {
+        let theme = self.themes[self.theme_idx].clone();
 
-        match ch {
-            'K' => {
-                state.wk_pos = (to.x, to.y);
-                state.castling &= 0b0011;
+        // Boards change size with the variant
+        self.sq_size = Self::square_size(self.settings.window_width, self.settings.window_height, &self.state);
 
-                if (from.x, from.y) == (4, 7) {
-                    if (to.x, to.y) == (6, 7) {
-                        state.board[7][5] = 'R';
-                        state.board[7][7] = '-';
-                    } else if (to.x, to.y) == (2, 7) {
-                        state.board[7][3] = 'R';
-                        state.board[7][0] = '-';
-                    }
-                }
-            }
+        // a1 is always dark, whatever the size
+        let height = self.state.height;
+        let light = |x: u8, y: u8| (x + height - 1 - y) % 2 == 1;
 
-            'k' => {
-                state.bk_pos = (to.x, to.y);
-                state.castling &= 0b1100;
+        for (x, y) in rules::squares(&self.state) {
+            self.board[y as usize][x as usize] = if light(x, y) { theme.light } else { theme.dark };
+        }
 
-                if (from.x, from.y) == (4, 0) {
-                    if (to.x, to.y) == (6, 0) {
-                        state.board[0][5] = 'r';
-                        state.board[0][7] = '-';
-                    } else if (to.x, to.y) == (2, 0) {
-                        state.board[0][3] = 'r';
-                        state.board[0][0] = '-';
-                    }
-                }
-            }
+        for (x, y) in rules::goal_squares(&self.state) {
+            self.board[y as usize][x as usize] = if light(x, y) {
+                theme.hill_light
+            } else {
+                theme.hill_dark
+            };
+        }
 
-            'p' => {
-                if from.y == 1 && to.y == 3 {
-                    state.enp_b = 0x80 >> from.x;
-                } else if from.y == 4 && from.x != to.x && state.board[5][to.x as usize] == '-' {
-                    state.board[4][to.x as usize] = '-';
-                }
+        for (plyr, check) in [(Player::White, self.state.w_check), (Player::Black, self.state.b_check)].iter() {
+            if let Some((x, y)) = self.state.king(*plyr).filter(|_| *check) {
+                self.board[y as usize][x as usize] = theme.check;
             }
+        }
 
-            'P' => {
-                if from.y == 6 && to.y == 4 {
-                    state.enp_w = 0x80 >> from.x;
-                } else if from.y == 3 && from.x != to.x && state.board[2][to.x as usize] == '-' {
-                    state.board[3][to.x as usize] = '-';
-                }
+        if let Some(mv) = self.hint {
+            for (x, y) in [mv.from, mv.to].iter() {
+                self.board[*y as usize][*x as usize] = theme.selection;
             }
+        }
+    }
 
-            'r' => {
-                if from.x == 0 && from.y == 0 {
-                    state.castling &= 0b1101;
-                } else if from.x == 7 && from.y == 0 {
-                    state.castling &= 0b1110;
-                }
-            }
+    // Switch to the next theme, keeping the current selection highlighted
+    fn next_theme(&mut self) {
+        self.theme_idx = (self.theme_idx + 1) % self.themes.len();
+        self.reset_board();
 
-            'R' => {
-                if from.x == 0 && from.y == 7 {
-                    state.castling &= 0b0111;
-                } else if from.x == 7 && from.y == 7 {
-                    state.castling &= 0b1011;
-                }
+        match (self.current_pos, self.current) {
+            (Some((x, y)), _) => {
+                self.moves.clear();
+                self.select_piece(x, y);
             }
-
+            (None, Some(pc)) if self.moving => self.select_drop(pc),
             _ => (),
         }
 
-        state.board[to.y as usize][to.x as usize] = ch;
-        state.board[y][x] = '-';
+        self.settings.theme = self.theme().spec();
+        self.settings.save();
+        self.needs_draw = true;
+    }
 
-        state.b_check = Self::check_for_checks(Player::Black, state);
-        state.w_check = Self::check_for_checks(Player::White, state);
-        if state.w_check { state.b_checks = state.b_checks+1; }
-        if state.b_check { state.w_checks = state.w_checks+1; }
+    fn flip_board(&mut self) {
+        self.settings.flipped = !self.settings.flipped;
+        self.settings.save();
+        self.needs_draw = true;
     }
 
-    fn get_line_moves(pos: &Point<u8>, dpos: Point<i8>, state: &BoardState) -> Vec<(u8, u8)> {
-        let mut m_x = pos.x as i8 + dpos.x;
-        let mut m_y = pos.y as i8 + dpos.y;
+    fn next_variant(&mut self) {
+        self.settings.variant = self.settings.variant.next();
+        self.settings.save();
+        self.new_game();
+    }
 
-        let mut moves = Vec::<(u8, u8)>::with_capacity(7);
+    fn toggle_chess960(&mut self) {
+        self.settings.variant.chess960 = !self.settings.variant.chess960;
+        self.settings.save();
+        self.new_game();
+    }
 
-        while m_x >= 0 && m_x < 8 && m_y >= 0 && m_y < 8 {
-            let ch = state.board[m_y as usize][m_x as usize];
+    // Cycle no computer -> computer plays black -> computer plays white
+    fn next_computer(&mut self) {
+        self.cancel_thinking();
+        self.settings.computer = match self.settings.computer {
+            None => Some(Player::Black),
+            Some(Player::Black) => Some(Player::White),
+            Some(Player::White) => None,
+        };
+        self.settings.save();
 
-            if Self::is_piece(ch) {
-                if Self::is_opponent(state.player, ch) {
-                    moves.push((m_x as u8, m_y as u8));
-                }
+        self.message = Some(self.computer_label());
+        self.needs_draw = true;
+    }
 
-                break;
+    // A fresh board behind the new game menu
+    fn open_menu(&mut self) {
+        self.new_game();
+        self.menu = true;
+        self.needs_draw = true;
+    }
+
+    fn menu_text(&self) -> String {
+        let clock = self.settings.clock.map_or(String::from("none"), |tc| tc.label());
+
+        format!(
+            "New game\n\nV  Variant: {}\nC  Clock: {}\nA  {}\nD  Level: {}  (Left/Right)\n\nEnter - start\nEscape - quit",
+            self.settings.variant.name(),
+            clock,
+            self.computer_label(),
+            skill::label(self.settings.ai_strength)
+        )
+    }
+
+    fn computer_label(&self) -> String {
+        match self.settings.computer {
+            Some(Player::White) => String::from("Computer plays white"),
+            Some(Player::Black) => String::from("Computer plays black"),
+            None => String::from("Computer off"),
+        }
+    }
+
+    // Step through the skill levels, wrapping around at both ends
+    fn change_level(&mut self, delta: i8) {
+        let levels = skill::MAX_LEVEL as i8 + 1;
+        self.settings.ai_strength = (self.settings.ai_strength.min(skill::MAX_LEVEL) as i8 + delta).rem_euclid(levels) as u8;
+        self.settings.save();
+
+        self.message = Some(format!("Level {}", skill::label(self.settings.ai_strength)));
+        self.needs_draw = true;
+    }
+
+    fn computer_to_move(&self) -> bool {
+        let over = self.state.w_win || self.state.b_win || self.state.draw;
+
+        self.settings.computer == Some(self.state.player) && !over && self.resume.is_none() && !self.menu
+    }
+
+    // Think on the computer's own clock, or for a fixed time without one.
+    // The move comes back to `update` through `Thinking`
+    fn start_thinking(&mut self) {
+        // Openings come straight from the book while it knows the position
+        if let Some(mv) = self.book.as_ref().and_then(|book| book.pick(&self.state)) {
+            self.play_move(mv);
+            return;
+        }
+
+        let mut limits = Limits { level: Some(skill::level(self.settings.ai_strength)), ..Limits::default() };
+
+        match self.time_control {
+            Some(tc) => {
+                let remaining = match self.state.player {
+                    Player::White => self.w_time,
+                    Player::Black => self.b_time,
+                };
+                let increment = Duration::from_secs(tc.increment_secs as u64);
+                limits.clock = Some(Clock { remaining, increment, moves_to_go: None });
             }
+            None => limits.movetime = Some(COMPUTER_MOVETIME),
+        }
+
+        self.spawn_search(Job::Move, limits);
+    }
 
-            moves.push((m_x as u8, m_y as u8));
-            m_x += dpos.x;
-            m_y += dpos.y;
+    // Start whatever background search is due: the computer's move, a hint
+    // or the evaluation bar's analysis, in that order
+    fn start_search(&mut self) {
+        if self.computer_to_move() {
+            self.start_thinking();
+            return;
         }
 
-        moves
+        let over = self.state.w_win || self.state.b_win || self.state.draw;
+        if over && self.review_wanted {
+            self.spawn_review();
+        }
+        if over || self.resume.is_some() || self.menu {
+            return;
+        }
+
+        if self.hint_wanted {
+            self.hint_wanted = false;
+            self.spawn_search(Job::Hint, Limits { movetime: Some(HINT_MOVETIME), ..Limits::default() });
+        } else if self.settings.eval_bar && self.evaluation.is_none() {
+            self.spawn_search(Job::Analysis, Limits { movetime: Some(ANALYSIS_MOVETIME), ..Limits::default() });
+        }
     }
 
-    fn mv_pawn(pos: Point<u8>, state: &BoardState) -> Vec<(u8, u8)> {
-        let x_i = pos.x as usize;
-        let y_i = pos.y as usize;
+    fn spawn_search(&mut self, job: Job, limits: Limits) {
+        let mut search = match self.search.take() {
+            Some(search) => search,
+            None => return,
+        };
 
-        let mut moves = Vec::<(u8, u8)>::with_capacity(4);
+        let stop = search.stop_flag();
+        stop.store(false, Ordering::Relaxed);
+        if let Ok(start) = BoardState::from_fen(&self.start_fen, self.state.variant) {
+            search.set_history(search::game_keys(&start, &self.history));
+        }
 
-        match state.player {
-            Player::White => {
-                if pos.y == 0 {
-                    return moves;
-                }
+        let (tx, reply) = mpsc::channel();
+        let state = self.state.clone();
+        thread::spawn(move || {
+            let mut last = None;
+            let mv = search.go(&state, &limits, |info| {
+                last = Some(Info { pv: info.pv.clone(), ..*info });
+            });
+            let _ = tx.send((search, mv, last));
+        });
+
+        self.thinking = Some(Thinking { job, stop, reply, cancelled: false });
+    }
 
-                if !Self::is_piece(state.board[y_i - 1][x_i]) {
-                    moves.push((pos.x, pos.y - 1));
-                }
+    fn spawn_review(&mut self) {
+        let mut search = match self.search.take() {
+            Some(search) => search,
+            None => return,
+        };
+        let start = match BoardState::from_fen(&self.start_fen, self.state.variant) {
+            Ok(start) => start,
+            Err(e) => {
+                eprintln!("Could not analyse the game: {}", e);
+                self.search = Some(search);
+                self.review_wanted = false;
+                return;
+            }
+        };
 
-                if pos.y == 6 && !Self::is_piece(state.board[y_i - 2][x_i]) {
-                    moves.push((pos.x, pos.y - 2));
-                }
+        let stop = search.stop_flag();
+        stop.store(false, Ordering::Relaxed);
+        let progress = Arc::new(AtomicUsize::new(0));
+        let counter = Arc::clone(&progress);
+
+        let (tx, reply) = mpsc::channel();
+        let moves = self.history.clone();
+        thread::spawn(move || {
+            let annotations = analysis::analyse(&start, &moves, &mut search, REVIEW_MOVETIME, |idx| {
+                counter.store(idx, Ordering::Relaxed);
+            });
+            let _ = tx.send((search, annotations));
+        });
+
+        self.review_wanted = false;
+        self.review = Some(Review { stop, progress, reply });
+    }
 
-                if (pos.x < 7 && Self::is_opponent(state.player, state.board[y_i - 1][x_i + 1]))
-                    || (pos.y == 3 && pos.x < 7 && state.enp_b & (0x80 >> (pos.x + 1)) > 0)
-                {
-                    moves.push((pos.x + 1, pos.y - 1));
-                }
+    // Keep the annotated moves and write them out as PGN once the analysis
+    // is done
+    fn poll_review(&mut self) {
+        let review = match &self.review {
+            Some(review) => review,
+            None => return,
+        };
 
-                if (pos.x > 0 && Self::is_opponent(state.player, state.board[y_i - 1][x_i - 1]))
-                    || (pos.y == 3 && pos.x > 0 && state.enp_b & (0x80 >> (pos.x - 1)) > 0)
-                {
-                    moves.push((pos.x - 1, pos.y - 1));
+        match review.reply.try_recv() {
+            Ok((search, annotations)) => {
+                self.review = None;
+                self.search = Some(search);
+                self.needs_draw = true;
+
+                if let Some(annotations) = annotations {
+                    self.annotations = annotations;
+                    self.export_pgn();
                 }
             }
+            Err(TryRecvError::Empty) => self.needs_draw = true,
+            Err(TryRecvError::Disconnected) => {
+                eprintln!("The game analysis stopped unexpectedly");
+                self.review = None;
+                self.search = Some(new_search(&self.settings));
+                self.needs_draw = true;
+            }
+        }
+    }
 
-            Player::Black => {
-                if pos.y == 7 {
-                    return moves;
-                }
+    fn cancel_review(&mut self) {
+        if let Some(review) = &self.review {
+            review.stop.store(true, Ordering::Relaxed);
+        }
+        self.review_wanted = false;
+        self.annotations.clear();
+    }
 
-                if !Self::is_piece(state.board[y_i + 1][x_i]) {
-                    moves.push((pos.x, pos.y + 1));
-                }
+    // Analyse the finished game, its moves show up with their annotations
+    fn ask_review(&mut self) {
+        let over = self.state.w_win || self.state.b_win || self.state.draw;
+        if !over || self.history.is_empty() || self.review.is_some() {
+            return;
+        }
 
-                if pos.y == 1 && !Self::is_piece(state.board[y_i + 2][x_i]) {
-                    moves.push((pos.x, pos.y + 2));
-                }
+        self.cancel_thinking();
+        self.annotations.clear();
+        self.review_wanted = true;
+        self.message = None;
+        self.needs_draw = true;
+    }
 
-                if (pos.x < 7 && Self::is_opponent(state.player, state.board[y_i + 1][x_i + 1]))
-                    || (pos.y == 4 && pos.x < 7 && state.enp_w & (0x80 >> (pos.x + 1)) > 0)
-                {
-                    moves.push((pos.x + 1, pos.y + 1));
-                }
+    fn export_pgn(&mut self) {
+        let names = match self.settings.computer {
+            Some(Player::White) => ("Computer", "Player"),
+            Some(Player::Black) => ("Player", "Computer"),
+            None => ("White", "Black"),
+        };
+        let pgn = analysis::to_pgn(&self.start_fen, &self.state, names, &self.annotations);
+
+        let result = match analysis::pgn_path() {
+            Some(path) => analysis::write_pgn(&pgn, &path).map(|_| path),
+            None => Err(String::from("no data directory")),
+        };
+
+        self.message = Some(match result {
+            Ok(path) => format!("Saved to {}", path.display()),
+            Err(e) => format!("Could not save the analysis: {}", e),
+        });
+    }
+
+    // Analysed moves a line per move number, flagged ones followed by the
+    // engine's move
+    fn move_list(&self) -> Vec<String> {
+        let mut lines: Vec<(u16, String)> = Vec::new();
 
-                if (pos.x > 0 && Self::is_opponent(state.player, state.board[y_i + 1][x_i - 1]))
-                    || (pos.y == 4 && pos.x > 0 && state.enp_w & (0x80 >> (pos.x - 1)) > 0)
-                {
-                    moves.push((pos.x - 1, pos.y + 1));
+        for a in self.annotations.iter() {
+            if lines.last().map_or(true, |(n, _)| *n != a.fullmove) {
+                let number = match a.player {
+                    Player::White => format!("{}.", a.fullmove),
+                    Player::Black => format!("{}. ...", a.fullmove),
+                };
+                lines.push((a.fullmove, number));
+            }
+
+            if let Some((_, line)) = lines.last_mut() {
+                line.push(' ');
+                line.push_str(&a.san);
+                if let Some(judgement) = a.judgement {
+                    line.push_str(judgement.symbol());
+                }
+                if let Some(best) = &a.best {
+                    line.push_str(&format!(" ({})", best));
                 }
             }
         }
 
-        moves
+        lines.into_iter().map(|(_, line)| line).collect()
     }
 
-    fn mv_knight(pos: Point<u8>, state: &BoardState) -> Vec<(u8, u8)> {
-        let x_m = pos.x as i8;
-        let y_m = pos.y as i8;
+    fn draw_move_list(&self, ctx: &mut Context, top: f32) -> GameResult<()> {
+        let text = self.theme().text;
 
-        let moves: Vec<(i8, i8)> = vec![
-            (-2, -1),
-            (-1, -2),
-            ( 1, -2),
-            ( 2, -1),
-            (-2,  1),
-            (-1,  2),
-            ( 1,  2),
-            ( 2,  1),
-        ];
+        if let Some(review) = &self.review {
+            let idx = review.progress.load(Ordering::Relaxed);
+            let msg = graphics::Text::new(format!("Analysing move {} of {}...", idx + 1, self.history.len()));
+            return graphics::draw(ctx, &msg, ([20., top], 0.0, text));
+        }
+
+        let (width, height) = (self.settings.window_width, self.settings.window_height);
+        let rows = ((height - top - 20.) / MOVE_LIST_LINE).max(1.) as usize;
+        let columns = ((width - 20.) / MOVE_LIST_COLUMN).max(1.) as usize;
+
+        let mut lines = self.move_list();
+        // Keep the end of the game in view when it does not fit
+        if lines.len() > rows * columns {
+            lines.drain(..lines.len() - rows * columns + 1);
+            lines.insert(0, String::from("..."));
+        }
+
+        for (idx, line) in lines.iter().enumerate() {
+            let x = 20. + (idx / rows) as f32 * MOVE_LIST_COLUMN;
+            let y = top + (idx % rows) as f32 * MOVE_LIST_LINE;
+            let msg = graphics::Text::new(line.as_str());
+            graphics::draw(ctx, &msg, ([x, y], 0.0, text))?;
+        }
+
+        Ok(())
+    }
+
+    // Play the computer's move, show the hint or update the evaluation bar
+    // once the search is done, unless it was cancelled
+    fn poll_thinking(&mut self) {
+        let thinking = match &self.thinking {
+            Some(thinking) => thinking,
+            None => return,
+        };
+
+        match thinking.reply.try_recv() {
+            Ok((search, mv, info)) => {
+                let (job, cancelled) = (thinking.job, thinking.cancelled);
+                self.thinking = None;
+                self.search = Some(search);
+                self.needs_draw = true;
+
+                if cancelled {
+                    return;
+                }
 
-        let mut poss_moves = Vec::<(u8, u8)>::with_capacity(8);
+                // A search that ends before its first iteration still counts
+                // as the analysis of this position, so it isn't started again
+                match &info {
+                    Some(info) => self.evaluation = Some(Evaluation::from_info(info, &self.state)),
+                    None if job == Job::Analysis => self.evaluation = Some(Evaluation::Centipawns(0)),
+                    None => (),
+                }
 
-        for (dx, dy) in moves {
-            let pos_x = x_m + dx;
-            let pos_y = y_m + dy;
-            if pos_x >= 0 && pos_x < 8 && pos_y >= 0 && pos_y < 8 {
-                let ch = state.board[pos_y as usize][pos_x as usize];
-                if !Self::is_piece(ch) || Self::is_opponent(state.player, ch) {
-                    poss_moves.push((pos_x as u8, pos_y as u8));
+                match (job, mv) {
+                    (Job::Move, Some(mv)) if self.computer_to_move() => self.play_move(mv),
+                    (Job::Hint, Some(mv)) => {
+                        self.hint = Some(mv);
+                        self.reset_board();
+                    }
+                    _ => (),
                 }
             }
+            Err(TryRecvError::Empty) => (),
+            Err(TryRecvError::Disconnected) => {
+                eprintln!("The computer's search stopped unexpectedly");
+                self.thinking = None;
+                self.search = Some(new_search(&self.settings));
+                self.needs_draw = true;
+            }
         }
+    }
 
-        poss_moves
+    // Stop the search under way, its searcher still comes back to `poll_thinking`
+    fn cancel_thinking(&mut self) {
+        if let Some(thinking) = &mut self.thinking {
+            thinking.cancelled = true;
+            thinking.stop.store(true, Ordering::Relaxed);
+        }
     }
 
-    fn mv_bishop(pos: Point<u8>, state: &BoardState) -> Vec<(u8, u8)> {
-        let mut moves = Vec::<(u8, u8)>::with_capacity(13);
-        moves.append(&mut Self::get_line_moves(&pos, Point::new( 1,  1), state));
-        moves.append(&mut Self::get_line_moves(&pos, Point::new( 1, -1), state));
-        moves.append(&mut Self::get_line_moves(&pos, Point::new(-1, -1), state));
-        moves.append(&mut Self::get_line_moves(&pos, Point::new(-1,  1), state));
-        moves
+    // The computer or a hint is being worked out, the evaluation bar's
+    // analysis runs quietly
+    fn thinking(&self) -> bool {
+        self.thinking.as_ref().map_or(false, |thinking| !thinking.cancelled && thinking.job != Job::Analysis)
     }
 
-    fn mv_rook(pos: Point<u8>, state: &BoardState) -> Vec<(u8, u8)> {
-        let mut moves = Vec::<(u8, u8)>::with_capacity(14);
-        for (dx, dy) in &[(0, 1), (0, -1), (1, 0), (-1, 0)] {
-            moves.append(&mut Self::get_line_moves(&pos, Point::new(*dx, *dy), state));
+    fn ask_hint(&mut self) {
+        if self.computer_to_move() {
+            return;
+        }
+
+        if self.thinking.as_ref().map_or(false, |thinking| thinking.job == Job::Analysis) {
+            self.cancel_thinking();
         }
+        self.hint_wanted = true;
+        self.needs_draw = true;
+    }
 
-        moves
+    fn toggle_eval_bar(&mut self) {
+        self.settings.eval_bar = !self.settings.eval_bar;
+        self.settings.save();
+        self.needs_draw = true;
     }
 
-    fn mv_queen(pos: Point<u8>, state: &BoardState) -> Vec<(u8, u8)> {
-        let mut moves = Vec::<(u8, u8)>::with_capacity(28);
-        for dy in -1..=1 {
-            for dx in -1..=1 {
-                if dx == 0 && dy == 0 {
-                    continue;
+    // Whatever was worked out for the old position no longer applies
+    fn position_changed(&mut self) {
+        self.cancel_thinking();
+        self.cancel_review();
+        self.hint = None;
+        self.hint_wanted = false;
+        self.evaluation = None;
+    }
+
+    // Take back the last move, and the computer's reply with it so it is the
+    // player's turn again. Duck moves go together with their piece move
+    fn undo(&mut self) {
+        let mut history = self.history.clone();
+        let mut state;
+
+        loop {
+            if history.pop().is_none() {
+                return;
+            }
+
+            state = match BoardState::from_fen(&self.start_fen, self.state.variant) {
+                Ok(state) => state,
+                Err(e) => {
+                    eprintln!("Could not undo: {}", e);
+                    return;
                 }
+            };
+            for mv in history.iter() {
+                state.make_move(*mv);
+            }
 
-                moves.append(&mut Self::get_line_moves(&pos, Point::new(dx, dy), state));
+            let computers_turn = self.settings.computer == Some(state.player);
+            if history.is_empty() || !(state.duck_to_move || computers_turn) {
+                break;
             }
         }
 
-        moves
+        self.position_changed();
+        self.state = state;
+        self.history = history;
+        self.current = None;
+        self.current_pos = None;
+        self.moves.clear();
+        self.moving = false;
+        self.explosion = None;
+        self.last_move = None;
+        self.message = Some(String::from("Move taken back"));
+        self.reset_board();
+        self.autosave();
+        self.needs_draw = true;
     }
 
-    fn mv_king(pos: Point<u8>, state: &BoardState) -> Vec<(u8, u8)> {
-        let mut moves = Vec::<(u8, u8)>::with_capacity(8);
-        for dy in -1..=1 {
-            for dx in -1..=1 {
-                if pos.x == 0 && pos.y == 0 {
-                    continue;
-                }
+    // The player gives up, against the computer whoever's turn it is
+    fn resign(&mut self) {
+        let over = self.state.w_win || self.state.b_win || self.state.draw;
+        if over {
+            return;
+        }
 
-                let x_m = pos.x as i8 + dx;
-                let y_m = pos.y as i8 + dy;
+        self.cancel_thinking();
 
-                if x_m >= 0 && x_m < 8 && y_m >= 0 && y_m < 8 {
-                    let ch = state.board[y_m as usize][x_m as usize];
-                    if !Self::is_piece(ch) || Self::is_opponent(state.player, ch) {
-                        moves.push((x_m as u8, y_m as u8));
-                    }
-                }
-            }
+        let loser = match self.settings.computer {
+            Some(computer) => computer.switch(),
+            None => self.state.player,
+        };
+
+        match loser {
+            Player::White => self.black_won(),
+            Player::Black => self.white_won(),
         }
+    }
 
-        let ch = state.board[pos.y as usize][pos.x as usize];
+    fn next_clock(&mut self) {
+        self.settings.clock = TimeControl::next(self.settings.clock);
+        self.settings.save();
+        self.new_game();
+    }
 
-        let (checked, q_side, k_side, plyr) = match ch {
-            'k' => (
-                state.b_check,
-                state.castling & 0b0010 > 0,
-                state.castling & 0b0001 > 0,
-                Player::Black,
-            ),
-            'K' => (
-                state.w_check,
-                state.castling & 0b1000 > 0,
-                state.castling & 0b0100 > 0,
-                Player::White,
-            ),
-            _ => (false, false, false, Player::White),
+    // Count down the side to move, flagging ends the game
+    fn tick_clock(&mut self, dt: Duration) {
+        let over = self.state.w_win || self.state.b_win || self.state.draw;
+        if !self.clock_running || over || self.resume.is_some() {
+            return;
+        }
+
+        let left = match self.state.player {
+            Player::White => &mut self.w_time,
+            Player::Black => &mut self.b_time,
         };
 
-        if checked {
-            return moves;
+        *left = left.saturating_sub(dt);
+
+        if *left == Duration::ZERO {
+            match self.state.player {
+                Player::White => self.black_won(),
+                Player::Black => self.white_won(),
+            }
         }
 
-        let y = pos.y as usize;
+        self.needs_draw = true;
+    }
 
-        if k_side {
-            let mut accept = true;
-            for x in 5..=6 {
-                if Self::is_piece(state.board[y][x as usize]) {
-                    accept = false;
-                    break;
-                }
-                let mut state_ = state.clone();
-                Self::move_piece_to(pos.clone(), Point::new(x, pos.y), &mut state_);
-                let checked = match plyr {
-                    Player::White => state_.w_check,
-                    Player::Black => state_.b_check,
-                };
+    // Called after a move, adds the increment for the side that just moved
+    fn punch_clock(&mut self) {
+        let tc = match self.time_control {
+            Some(tc) => tc,
+            None => return,
+        };
 
-                if checked {
-                    accept = false;
-                    break;
-                }
-            }
+        let inc = Duration::from_secs(tc.increment_secs as u64);
+        match self.state.player.switch() {
+            Player::White => self.w_time += inc,
+            Player::Black => self.b_time += inc,
+        }
 
-            if accept {
-                moves.push((6, pos.y));
+        self.clock_running = true;
+    }
+
+    // Save the window size once resizing has stopped for a moment
+    fn tick_resize(&mut self, dt: Duration) {
+        if let Some(left) = self.resize_save {
+            match left.checked_sub(dt) {
+                Some(left) if left > Duration::ZERO => self.resize_save = Some(left),
+                _ => self.save_window_size(),
             }
         }
+    }
 
-        if q_side {
-            let mut accept = true;
-            for x in 2..=3 {
-                if Self::is_piece(state.board[y][x as usize]) {
-                    accept = false;
-                    break;
-                }
-                let mut state_ = state.clone();
-                Self::move_piece_to(pos.clone(), Point::new(x, pos.y), &mut state_);
-                let checked = match plyr {
-                    Player::White => state_.w_check,
-                    Player::Black => state_.b_check,
-                };
+    fn save_window_size(&mut self) {
+        if self.resize_save.take().is_some() {
+            self.settings.save();
+        }
+    }
 
-                if checked {
-                    accept = false;
-                    break;
-                }
+    fn format_clock(time: Duration) -> String {
+        let secs = time.as_secs();
+        format!("{}:{:02}", secs / 60, secs % 60)
+    }
+
+    // Stand-in for fairy pieces without an image, a disc in the piece's
+    // colour with its letter on it
+    fn draw_letter_piece(&self, ctx: &mut Context, ch: char, x_sq: f32, y_sq: f32) -> GameResult<()> {
+        let sq = self.sq_size as f32;
+        let (fill, ink) = match rules::is_white_piece(ch) {
+            true => (Color::WHITE, Color::BLACK),
+            false => (Color::BLACK, Color::WHITE),
+        };
+
+        let center = [x_sq + sq / 2., y_sq + sq / 2.];
+        let disc = graphics::Mesh::new_circle(ctx, DrawMode::fill(), center, sq * 0.35, 1., fill)?;
+        graphics::draw(ctx, &disc, DrawParam::default())?;
+        let ring = graphics::Mesh::new_circle(ctx, DrawMode::stroke(2.), center, sq * 0.35, 1., ink)?;
+        graphics::draw(ctx, &ring, DrawParam::default())?;
+
+        let mut letter = graphics::Text::new(ch.to_ascii_uppercase().to_string());
+        letter.set_font(graphics::Font::default(), graphics::PxScale::from(sq * 0.4));
+        let dest = [center[0] - letter.width(ctx) / 2., center[1] - letter.height(ctx) / 2.];
+        graphics::draw(ctx, &letter, (dest, 0.0, ink))
+    }
+
+    fn white_won(&mut self) {
+        self.state.w_win = true;
+        self.clear_autosave();
+        self.needs_draw = true;
+    }
+
+    fn black_won(&mut self) {
+        self.state.b_win = true;
+        self.clear_autosave();
+        self.needs_draw = true;
+    }
+
+    fn drawn(&mut self) {
+        self.state.draw = true;
+        self.clear_autosave();
+        self.needs_draw = true;
+    }
+
+    fn to_saved(&self) -> SavedGame {
+        SavedGame {
+            start_fen: self.start_fen.clone(),
+            moves: self.history.iter().map(|mv| mv.to_text(self.state.height)).collect(),
+            white_checks: self.state.w_checks,
+            black_checks: self.state.b_checks,
+            white_ms: self.w_time.as_millis() as u64,
+            black_ms: self.b_time.as_millis() as u64,
+            clock_running: self.clock_running,
+            variant: self.state.variant,
+            clock: self.time_control,
+        }
+    }
+
+    fn load_game(&mut self, saved: SavedGame) -> Result<(), String> {
+        let (state, history) = saved.replay()?;
+        self.position_changed();
+
+        self.state = state;
+        self.history = history;
+        self.book = Book::for_variant(self.state.variant);
+        self.start_fen = saved.start_fen;
+        self.chess960_index = None;
+        self.time_control = saved.clock;
+        self.w_time = Duration::from_millis(saved.white_ms);
+        self.b_time = Duration::from_millis(saved.black_ms);
+        self.clock_running = saved.clock_running;
+
+        self.current = None;
+        self.current_pos = None;
+        self.moves.clear();
+        self.moving = false;
+        self.message = None;
+        self.last_move = self.history.last().map(|mv| mv.to_text(self.state.height));
+        self.reset_board();
+        self.needs_draw = true;
+
+        Ok(())
+    }
+
+    // Load a game given on the command line
+    pub fn load_game_file(&mut self, path: &Path) {
+        let result = SavedGame::load(path).and_then(|saved| self.load_game(saved));
+
+        match result {
+            Ok(()) => {
+                self.resume = None;
+                self.menu = false;
             }
+            Err(e) => eprintln!("Could not load game: {}", e),
+        }
+    }
 
-            if accept {
-                moves.push((2, pos.y));
+    fn autosave(&self) {
+        if let Some(path) = SavedGame::autosave_path() {
+            if let Err(e) = self.to_saved().write(&path) {
+                eprintln!("Autosave failed: {}", e);
             }
         }
+    }
 
-        moves
+    fn clear_autosave(&self) {
+        if let Some(path) = SavedGame::autosave_path() {
+            let _ = fs::remove_file(path);
+        }
     }
 
-    fn get_piece_moves(ch: char, pos: Point<u8>, state: &BoardState) -> Vec<(u8, u8)> {
-        match ch {
-            'p' | 'P' => Self::mv_pawn(pos, state),
-            'r' | 'R' => Self::mv_rook(pos, state),
-            'n' | 'N' => Self::mv_knight(pos, state),
-            'b' | 'B' => Self::mv_bishop(pos, state),
-            'q' | 'Q' => Self::mv_queen(pos, state),
-            'k' | 'K' => Self::mv_king(pos, state),
-            _ => Vec::<(u8, u8)>::new(),
+    fn save_game(&mut self) {
+        let result = match SavedGame::quicksave_path() {
+            Some(path) => self.to_saved().write(&path).map(|_| path),
+            None => Err(String::from("no data directory")),
+        };
+
+        self.message = Some(match result {
+            Ok(path) => format!("Saved to\n{}", path.display()),
+            Err(e) => format!("Save failed:\n{}", e),
+        });
+        self.needs_draw = true;
+    }
+
+    fn load_saved_game(&mut self) {
+        let result = match SavedGame::quicksave_path() {
+            Some(path) => SavedGame::load(&path).and_then(|saved| self.load_game(saved)),
+            None => Err(String::from("no data directory")),
+        };
+
+        if let Err(e) = result {
+            self.message = Some(format!("Load failed:\n{}", e));
         }
+        self.needs_draw = true;
     }
 
     fn select_piece(&mut self, x: u8, y: u8) {
-        let ch = self.board_pcs[y as usize][x as usize];
+        let ch = self.state.board[y as usize][x as usize];
 
-        if !Self::is_piece(ch) || Self::is_opponent(self.turn, ch) {
+        if !rules::is_piece(ch) || rules::is_opponent(self.state.player, ch) {
             return;
         }
 
-        let board_state = self.get_board_state();
-
-        let moves = Self::get_piece_moves(ch, Point::new(x, y), &board_state);
-
         self.current = Some(ch);
         self.current_pos = Some((x, y));
 
-        for (m_x, m_y) in &moves {
-            let mut state = board_state.clone();
-            Self::move_piece_to(Point::new(x, y), Point::new(*m_x, *m_y), &mut state);
-            let checked = match self.turn {
-                Player::White => state.w_check,
-                Player::Black => state.b_check,
-            };
-
-            if checked {
-                continue;
-            }
+        for (m_x, m_y) in rules::legal_targets((x, y), &self.state) {
+            self.board[m_y as usize][m_x as usize] = self.theme().move_target;
 
-            self.board[*m_y as usize][*m_x as usize] = Color::from_rgb(200, 200, 0);
+            self.moves.push((m_x, m_y));
 
-            self.moves.push((*m_x, *m_y));
+            // Castling can be played by clicking the rook or the king's destination
+            if let Some((d_x, d_y)) = rules::castling_destination(Move::new((x, y), (m_x, m_y)), &self.state) {
+                if !self.moves.contains(&(d_x, d_y)) {
+                    self.board[d_y as usize][d_x as usize] = self.theme().move_target;
+                    self.moves.push((d_x, d_y));
+                }
+            }
         }
 
-        self.board[y as usize][x as usize] = Color::from_rgb(255, 85, 85);
+        self.board[y as usize][x as usize] = self.theme().selection;
 
         self.needs_draw = true;
         self.moving = true;
     }
 
-    fn is_white_to_move(&mut self) -> bool{
-        match self.turn {
-            Player::White => true,
-            Player::Black => false,
+    // Pick a Crazyhouse pocket piece, the squares it can be dropped on light up
+    fn select_drop(&mut self, pc: char) {
+        self.moves.clear();
+        self.reset_board();
+
+        self.current = Some(pc);
+        self.current_pos = None;
+
+        for mv in rules::legal_drops(&self.state).into_iter().filter(|mv| mv.drop == Some(pc)) {
+            self.board[mv.to.1 as usize][mv.to.0 as usize] = self.theme().move_target;
+            self.moves.push(mv.to);
         }
+
+        self.needs_draw = true;
+        self.moving = true;
     }
 
-    fn move_piece(&mut self, x: u8, y: u8) -> bool {
+    fn move_piece(&mut self, x: u8, y: u8) {
         if self.moves.contains(&(x, y)) {
-            let mut state = self.get_board_state();
-            let curr = self.current_pos.unwrap();
-            Self::move_piece_to(Point::new(curr.0, curr.1), Point::new(x, y), &mut state);
-            self.set_state(&state);
-            self.current = None;
-            self.current_pos = None;
-            self.moving = false;
-            self.turn = self.turn.switch();
-            state.player = state.player.switch();
-            self.moves.clear();
-            self.needs_draw = true;
-            self.reset_board();
+            let from = match (self.current_pos, self.current) {
+                (Some(from), _) => from,
+                (None, Some(pc)) => {
+                    self.play_move(Move::new_drop(pc, (x, y)));
+                    return;
+                }
+                (None, None) => return,
+            };
+            let mut mv = Move::new(from, (x, y));
 
-            if Self::check_for_checkmate(self.turn, &state) {
-                return true;
-            } else {
-                return false;
+            // Mouse promotions always make a queen, type e8=N for anything else
+            let ch = self.state.board[from.1 as usize][from.0 as usize];
+            if rules::promotes(ch, (x, y), &self.state) {
+                mv.promotion = Some('Q');
             }
+
+            if let Some(mv) = rules::resolve_move(mv, &self.state) {
+                self.play_move(mv);
+            }
+            return;
         }
 
-        let ch = self.board_pcs[y as usize][x as usize];
+        let ch = self.state.board[y as usize][x as usize];
 
-        if Self::is_piece(ch) && !Self::is_opponent(self.turn, ch) {
+        if rules::is_piece(ch) && !rules::is_opponent(self.state.player, ch) {
             self.moves.clear();
             self.reset_board();
             self.select_piece(x, y);
             self.needs_draw = true;
         }
-
-        false
     }
 
-    fn set_state(&mut self, state: &BoardState) {
-        self.board_pcs = state.board;
-        self.w_king_pos = state.wk_pos;
-        self.b_king_pos = state.bk_pos;
-        self.enp_b = state.enp_b;
-        self.enp_w = state.enp_w;
-        self.castling = state.castling;
-        self.w_check = state.w_check;
-        self.b_check = state.b_check;
-        self.w_checks = state.w_checks;
-        self.b_checks = state.b_checks;
-    }
-
-    fn check_for_checks(plyr: Player, state: &mut BoardState) -> bool {
-        let orig = state.player;
-        state.player = plyr.switch();
-        for y in 0..8 {
-            for x in 0..8 {
-                let ch = state.board[y as usize][x as usize];
-
-                let is_valid_piece = match plyr {
-                    Player::White => Self::is_black_piece(ch),
-                    Player::Black => Self::is_white_piece(ch),
-                };
-
-                if !is_valid_piece {
-                    continue;
-                }
+    // Play a legal move and check whether it ended the game
+    fn play_move(&mut self, mv: Move) {
+        let san = notation::to_san(mv, &self.state);
 
-                let k_pos = match plyr {
-                    Player::White => &state.wk_pos,
-                    Player::Black => &state.bk_pos,
-                };
+        // The duck finishes the turn, show it after the piece move
+        self.last_move = match (mv.drop, self.last_move.take()) {
+            (Some(rules::DUCK), Some(piece_move)) => Some(format!("{} {}", piece_move, san)),
+            _ => Some(san),
+        };
 
-                if Self::get_piece_moves(ch, Point::new(x, y), state).contains(k_pos) {
-                    state.player = orig;
-                    return true;
-                }
-            }
+        let ch = self.state.board[mv.from.1 as usize][mv.from.0 as usize];
+        let capture = rules::is_opponent(self.state.player, self.state.board[mv.to.1 as usize][mv.to.0 as usize])
+            || ((ch == 'p' || ch == 'P') && mv.from.0 != mv.to.0);
+        if capture && self.state.variant.kind == Kind::Atomic {
+            self.explosion = Some((mv.to, EXPLOSION_TIME));
         }
 
-        state.player = orig;
-        false
+        self.state.make_move(mv);
+        self.history.push(mv);
+        self.position_changed();
+        if !self.state.duck_to_move {
+            self.punch_clock();
+        }
+        self.current = None;
+        self.current_pos = None;
+        self.moving = false;
+        self.moves.clear();
+        self.message = match self.state.duck_to_move {
+            true => Some(String::from("Click an empty square\nfor the duck")),
+            false => None,
+        };
+        self.needs_draw = true;
+        self.reset_board();
+        self.autosave();
+
+        match rules::outcome(&self.state) {
+            Some(Outcome::Win(Player::White)) => self.white_won(),
+            Some(Outcome::Win(Player::Black)) => self.black_won(),
+            Some(Outcome::Draw) => self.drawn(),
+            None => (),
+        }
     }
 
-    fn check_for_checkmate(plyr: Player, state: &BoardState) -> bool {
-        if  state.board[4][4] == 'k' || state.board[4][4] == 'K' ||
-            state.board[3][4] == 'k' || state.board[3][4] == 'K' ||
-            state.board[4][3] == 'k' || state.board[4][3] == 'K' ||
-            state.board[3][3] == 'k' || state.board[3][3] == 'K' ||
-            state.w_checks == 3 || state.b_checks == 3  {
-                return true;
+    // Same as clicking on the square
+    fn click_square(&mut self, x: u8, y: u8) {
+        if self.computer_to_move() || x >= self.state.width || y >= self.state.height {
+            return;
+        }
+
+        if self.state.duck_to_move {
+            if let Some(mv) = rules::resolve_move(Move::new_duck((x, y)), &self.state) {
+                self.play_move(mv);
+            }
+        } else if !self.moving {
+            self.select_piece(x, y);
         } else {
-            for y in 0..8 {
-                for x in 0..8 {
-                    let ch = state.board[y as usize][x as usize];
+            self.move_piece(x, y);
+        }
+    }
 
-                    let is_valid_piece = match plyr {
-                        Player::White => Self::is_white_piece(ch),
-                        Player::Black => Self::is_black_piece(ch),
-                    };
+    fn submit_input(&mut self) {
+        if self.computer_to_move() {
+            return;
+        }
 
-                    if !is_valid_piece {
-                        continue;
-                    }
+        match notation::parse_move(&self.input, &self.state) {
+            Ok(mv) => {
+                self.input.clear();
+                self.play_move(mv);
+            }
+            Err(e) => self.message = Some(e),
+        }
 
-                    for (m_x, m_y) in Self::get_piece_moves(ch, Point::new(x, y), state) {
-                        let mut state_ = state.clone();
-                        Self::move_piece_to(Point::new(x, y), Point::new(m_x, m_y), &mut state_);
+        self.needs_draw = true;
+    }
 
-                        let checked = match plyr {
-                            Player::White => state_.w_check,
-                            Player::Black => state_.b_check,
-                        };
+    // Arrow keys move the cursor as seen on screen, so flip with the board
+    fn move_cursor(&mut self, dx: i8, dy: i8) {
+        let (dx, dy) = if self.settings.flipped { (-dx, -dy) } else { (dx, dy) };
 
-                        if !checked {
-                            return false;
-                        }
-                    }
-                }
-            }
+        self.cursor = Some(match self.cursor {
+            Some((x, y)) => (
+                (x as i8 + dx).clamp(0, self.state.width as i8 - 1) as u8,
+                (y as i8 + dy).clamp(0, self.state.height as i8 - 1) as u8,
+            ),
+            // Start on the king, or the first piece of a side without one
+            None => self.state.king(self.state.player).unwrap_or_else(|| {
+                let player = self.state.player;
+                rules::squares(&self.state)
+                    .find(|(x, y)| {
+                        let ch = self.state.board[*y as usize][*x as usize];
+                        rules::is_piece(ch) && ch != rules::DUCK && !rules::is_opponent(player, ch)
+                    })
+                    .unwrap_or((0, 0))
+            }),
+        });
+
+        self.needs_draw = true;
+    }
+
+    fn cancel(&mut self, ctx: &mut Context) {
+        if !self.input.is_empty() {
+            self.input.clear();
+        } else if self.moving {
+            self.current = None;
+            self.current_pos = None;
+            self.moving = false;
+            self.moves.clear();
+            self.reset_board();
+        } else if self.cursor.is_some() {
+            self.cursor = None;
+        } else {
+            self.save_window_size();
+            event::quit(ctx);
         }
-        true
+
+        self.message = None;
+        self.needs_draw = true;
     }
 }
 
 impl EventHandler<ggez::GameError> for RChess {
-    fn update(&mut self, _ctx: &mut Context) -> GameResult<()> {
+    fn update(&mut self, ctx: &mut Context) -> GameResult<()> {
+        let dt = timer::delta(ctx);
+        self.tick_clock(dt);
+        self.tick_resize(dt);
+
+        self.poll_thinking();
+        self.poll_review();
+        if self.thinking.is_none() && self.review.is_none() {
+            self.start_search();
+        }
+        // Keep the thinking indicator moving
+        if self.thinking() {
+            self.needs_draw = true;
+        }
+
+        if let Some((sq, left)) = self.explosion {
+            self.explosion = Some((sq, left.saturating_sub(dt))).filter(|(_, left)| !left.is_zero());
+            self.needs_draw = true;
+        }
+
         Ok(())
     }
 
@@ -781,81 +1354,360 @@ impl EventHandler<ggez::GameError> for RChess {
         }
         graphics::clear(ctx, Color::from_rgb(0, 0, 0));
 
-        for (y, row) in self.board.iter().enumerate() {
-            for (x, cell) in row.iter().enumerate() {
-                let x_sq = x as i32 * self.sq_size;
-                let y_sq = y as i32 * self.sq_size;
+        for y_scr in 0..self.state.height as usize {
+            for x_scr in 0..self.state.width as usize {
+                let (x, y) = self.orient(x_scr, y_scr);
+                let x_sq = x_scr as i32 * self.sq_size;
+                let y_sq = y_scr as i32 * self.sq_size;
 
                 let r = graphics::Rect::new_i32(x_sq, y_sq, self.sq_size, self.sq_size);
-                let mesh = graphics::Mesh::new_rectangle(ctx, DrawMode::fill(), r, *cell)?;
+                let mesh = graphics::Mesh::new_rectangle(ctx, DrawMode::fill(), r, self.board[y][x])?;
 
                 graphics::draw(ctx, &mesh, DrawParam::default())?;
 
-                let ch = self.board_pcs[y][x];
+                let ch = self.state.board[y][x];
 
-                if Self::is_piece(ch) {
+                if rules::is_piece(ch) {
                     let img = match self.pieces.get(&ch) {
                         Some(i) => i,
-                        None => continue,
+                        None => {
+                            self.draw_letter_piece(ctx, ch, x_sq as f32, y_sq as f32)?;
+                            continue;
+                        }
                     };
 
-                    let ddraw = (self.sq_size as f32 - img.width() as f32 * 1.5) / 2.;
+                    let scale = 1.5 * self.sq_size as f32 / 100.;
+                    let ddraw = (self.sq_size as f32 - img.width() as f32 * scale) / 2.;
                     let x_draw = x_sq as f32 + ddraw;
                     let y_draw = y_sq as f32 + ddraw;
-                    let draw_param = DrawParam::new().dest([x_draw, y_draw]).scale([1.5, 1.5]);
+                    let draw_param = DrawParam::new().dest([x_draw, y_draw]).scale([scale, scale]);
 
                     graphics::draw(ctx, img, draw_param)?;
                 }
+
+                if self.cursor == Some((x as u8, y as u8)) {
+                    let r = graphics::Rect::new_i32(x_sq + 2, y_sq + 2, self.sq_size - 4, self.sq_size - 4);
+                    let color = self.theme().selection;
+                    let mesh = graphics::Mesh::new_rectangle(ctx, DrawMode::stroke(4.), r, color)?;
+                    graphics::draw(ctx, &mesh, DrawParam::default())?;
+                }
+            }
+        }
+
+        // Fading blast over the capture square and its neighbours
+        if let Some(((x, y), left)) = self.explosion {
+            let (x_scr, y_scr) = self.orient(x as usize, y as usize);
+            let sq = self.sq_size as f32;
+            let center = [(x_scr as f32 + 0.5) * sq, (y_scr as f32 + 0.5) * sq];
+            let fade = left.as_secs_f32() / EXPLOSION_TIME.as_secs_f32();
+            let color = Color::new(1., 0.55, 0., 0.7 * fade);
+            let mesh = graphics::Mesh::new_circle(ctx, DrawMode::fill(), center, sq * (1.5 - fade * 0.5), 1., color)?;
+            graphics::draw(ctx, &mesh, DrawParam::default())?;
+        }
+
+        let text = self.theme().text;
+        let (board_w, board_h) = self.board_px();
+        // The side panel keeps its layout on boards smaller than 8x8
+        let board_px = board_h.max(8. * self.sq_size as f32);
+
+        let prompt = graphics::Text::new(format!("Move: {}_", self.input));
+        graphics::draw(ctx, &prompt, ([10., board_h + 12.], 0.0, text))?;
+
+        if let Some(san) = &self.last_move {
+            let last = graphics::Text::new(format!("Last move: {}", san));
+            graphics::draw(ctx, &last, ([board_w.max(320.) / 2., board_h + 12.], 0.0, text))?;
+        }
+
+        let panel_x = board_w + 30.;
+        let (b_y, w_y) = self.panel_rows();
+
+        let mut title = self.state.variant.name();
+        if let Some(index) = self.chess960_index {
+            title.push_str(&format!("\nPosition #{}", index));
+        }
+        if let Some(tc) = self.time_control {
+            title.push_str(&format!("\n{}", tc.label()));
+        }
+        let title = graphics::Text::new(title);
+        graphics::draw(ctx, &title, ([panel_x - 20., 10.], 0.0, text))?;
+
+        if rules::checks_to_win(self.state.variant).is_some() {
+            let w_t = format!("{}{}", "White checks: ", self.state.w_checks);
+            let w_msg= graphics::Text::new(w_t);
+            let w_dest: ggez::mint::Point2<f32> = ggez::mint::Point2{x:panel_x, y:w_y};
+            graphics::draw(ctx, &w_msg, (w_dest, 0.0, text))?;
+
+            let b_t = format!("{}{}", "Black checks: ", self.state.b_checks);
+            let b_msg= graphics::Text::new(b_t);
+            let b_dest: ggez::mint::Point2<f32> = ggez::mint::Point2{x:panel_x, y:b_y};
+            graphics::draw(ctx, &b_msg, (b_dest, 0.0, text))?;
+        }
+
+        if self.time_control.is_some() {
+            let w_msg = graphics::Text::new(format!("White {}", Self::format_clock(self.w_time)));
+            let w_off = if self.settings.flipped { -50. } else { 50. };
+            graphics::draw(ctx, &w_msg, ([panel_x, w_y + w_off], 0.0, text))?;
+
+            let b_msg = graphics::Text::new(format!("Black {}", Self::format_clock(self.b_time)));
+            graphics::draw(ctx, &b_msg, ([panel_x, b_y - w_off], 0.0, text))?;
+        }
+
+        if self.state.variant.kind == Kind::Crazyhouse {
+            for (side, plyr) in [Player::White, Player::Black].iter().enumerate() {
+                for (idx, pc) in rules::POCKET_PIECES.iter().enumerate() {
+                    let count = self.state.pockets[side][idx];
+                    if count == 0 {
+                        continue;
+                    }
+
+                    let r = self.pocket_rect(*plyr, idx);
+                    let pc = if side == 0 { *pc } else { pc.to_ascii_lowercase() };
+
+                    if let Some(img) = self.pieces.get(&pc) {
+                        let scale = r.w / img.width().max(1) as f32;
+                        graphics::draw(ctx, img, DrawParam::new().dest([r.x, r.y]).scale([scale, scale]))?;
+                    }
+
+                    if count > 1 {
+                        let n = graphics::Text::new(count.to_string());
+                        graphics::draw(ctx, &n, ([r.x + r.w - 10., r.y + r.h - 14.], 0.0, text))?;
+                    }
+
+                    let selected = self.moving && self.current_pos.is_none() && self.current == Some(pc.to_ascii_uppercase());
+                    if selected && *plyr == self.state.player {
+                        let color = self.theme().selection;
+                        let mesh = graphics::Mesh::new_rectangle(ctx, DrawMode::stroke(2.), r, color)?;
+                        graphics::draw(ctx, &mesh, DrawParam::default())?;
+                    }
+                }
             }
         }
-        let w_t = format!("{}{}", "White checks: ", self.w_checks);
-        let w_msg= graphics::Text::new(w_t);
-        let w_dest: ggez::mint::Point2<f32> = ggez::mint::Point2{x:830.0, y:500.0};
-        graphics::draw(ctx, &w_msg, (w_dest, 0.0, Color::RED))?;
 
-        let b_t = format!("{}{}", "Black checks: ", self.b_checks);
-        let b_msg= graphics::Text::new(b_t);
-        let b_dest: ggez::mint::Point2<f32> = ggez::mint::Point2{x:830.0, y:300.0};
-        graphics::draw(ctx, &b_msg, (b_dest, 0.0, Color::RED))?;
+        if let Some(message) = &self.message {
+            let msg = graphics::Text::new(message.as_str());
+            graphics::draw(ctx, &msg, ([panel_x - 20., board_px - 160.], 0.0, text))?;
+        }
+
+        if let (true, Some(evaluation)) = (self.settings.eval_bar, self.evaluation) {
+            let msg = graphics::Text::new(evaluation.label());
+            graphics::draw(ctx, &msg, ([panel_x - 20., board_px - 230.], 0.0, text))?;
+        }
+
+        if self.settings.eval_bar {
+            // White fills the bar from white's side of the board
+            let white_h = board_h * self.evaluation.map_or(0.5, |e| e.white_share());
+            let white_y = if self.settings.flipped { 0. } else { board_h - white_h };
+
+            let back = graphics::Rect::new(board_w + 1., 0., EVAL_BAR_WIDTH, board_h);
+            let mesh = graphics::Mesh::new_rectangle(ctx, DrawMode::fill(), back, Color::from_rgb(40, 40, 40))?;
+            graphics::draw(ctx, &mesh, DrawParam::default())?;
+
+            let white = graphics::Rect::new(board_w + 1., white_y, EVAL_BAR_WIDTH, white_h);
+            let mesh = graphics::Mesh::new_rectangle(ctx, DrawMode::fill(), white, Color::from_rgb(235, 235, 235))?;
+            graphics::draw(ctx, &mesh, DrawParam::default())?;
+        }
+
+        if self.thinking() {
+            let dots = (timer::time_since_start(ctx).as_millis() / 400 % 4) as usize;
+            let msg = graphics::Text::new(format!("Thinking{}", ".".repeat(dots)));
+            graphics::draw(ctx, &msg, ([panel_x - 20., board_px - 185.], 0.0, text))?;
+        }
+
+        let help = graphics::Text::new("Ctrl + T theme  F flip\nV variant  R Chess960\nC clock  N new\nS save  L load\nA computer  D level\nZ undo  G resign\nH hint  E eval bar");
+        graphics::draw(ctx, &help, ([panel_x - 20., board_px - 118.], 0.0, text))?;
 
         self.needs_draw = false;
-        if self.w_win {
-            graphics::clear(ctx, Color::from_rgb(0, 0, 0)); 
-            let msg= graphics::Text::new("White won!");
-            let dest: ggez::mint::Point2<f32> = ggez::mint::Point2{x:400.0, y:400.0};
-            graphics::draw(ctx, &msg, (dest, 0.0, Color::RED))?;
+        let center: ggez::mint::Point2<f32> = ggez::mint::Point2{x:board_w / 2., y:board_h / 2.};
+        if self.resume.is_some() {
+            graphics::clear(ctx, Color::from_rgb(0, 0, 0));
+            let msg = graphics::Text::new("Continue your last game?\n\nEnter - continue\nN - new game");
+            graphics::draw(ctx, &msg, (center, 0.0, text))?;
+        } else if self.menu {
+            graphics::clear(ctx, Color::from_rgb(0, 0, 0));
+            let msg = graphics::Text::new(self.menu_text());
+            graphics::draw(ctx, &msg, ([board_w / 4., board_h / 3.], 0.0, text))?;
         }
-        if self.b_win {
+        let result = match (self.state.w_win, self.state.b_win, self.state.draw) {
+            (true, _, _) => Some("White won!"),
+            (_, true, _) => Some("Black won!"),
+            (_, _, true) => Some("Draw!"),
+            _ => None,
+        };
+        if let Some(result) = result {
             graphics::clear(ctx, Color::from_rgb(0, 0, 0));
-            let msg= graphics::Text::new("Black won!");
-            let dest: ggez::mint::Point2<f32> = ggez::mint::Point2{x:400.0, y:400.0};
-            graphics::draw(ctx, &msg, (dest, 0.0, Color::RED))?;
+
+            if self.review.is_none() && !self.review_wanted && self.annotations.is_empty() {
+                let msg = match self.history.is_empty() {
+                    true => graphics::Text::new(result),
+                    false => graphics::Text::new(format!("{}\n\nCtrl + P - analyse the game", result)),
+                };
+                graphics::draw(ctx, &msg, (center, 0.0, text))?;
+            } else {
+                // The analysed moves take the whole window
+                let mut header = result.to_string();
+                if let Some(message) = &self.message {
+                    header.push_str(&format!("   {}", message));
+                }
+                let msg = graphics::Text::new(header);
+                graphics::draw(ctx, &msg, ([20., 20.], 0.0, text))?;
+                self.draw_move_list(ctx, 60.)?;
+            }
         }
         graphics::present(ctx)
     }
 
     fn mouse_button_down_event(&mut self, _ctx: &mut Context, btn: MouseButton, x: f32, y: f32) {
-        let x = (x as i32 / self.sq_size) as u8;
-        let y = (y as i32 / self.sq_size) as u8;
+        if self.resume.is_some() || self.menu {
+            return;
+        }
+
+        if let Some(pc) = self.pocket_at(x, y) {
+            self.cursor = None;
+            self.select_drop(pc);
+            return;
+        }
+
+        let x = x as i32 / self.sq_size;
+        let y = y as i32 / self.sq_size;
+
+        if !(0..self.state.width as i32).contains(&x) || !(0..self.state.height as i32).contains(&y) {
+            return;
+        }
+
+        let (x, y) = self.orient(x as usize, y as usize);
+        let (x, y) = (x as u8, y as u8);
 
         match btn {
             MouseButton::Left => {
-                if !self.moving {
-                    self.select_piece(x, y);
-                } else {
-                    let mated = self.move_piece(x, y);
-
-                    if mated {
-                        if  self.is_white_to_move(){
-                            self.black_won();
-                        } else {
-                            self.white_won();
-                        }
+                self.cursor = None;
+                self.click_square(x, y);
+            }
+
+            _ => (),
+        }
+    }
+
+    fn key_down_event(&mut self, ctx: &mut Context, keycode: KeyCode, keymods: KeyMods, _repeat: bool) {
+        if let Some(saved) = self.resume.take() {
+            self.skip_text = true;
+
+            match keycode {
+                KeyCode::Return | KeyCode::Y => {
+                    if let Err(e) = self.load_game(saved) {
+                        self.message = Some(format!("Load failed:\n{}", e));
                     }
                 }
+                KeyCode::N => {
+                    self.clear_autosave();
+                    self.menu = true;
+                }
+                KeyCode::Escape => {
+                    self.save_window_size();
+                    event::quit(ctx);
+                }
+                _ => self.resume = Some(saved),
+            }
+
+            self.needs_draw = true;
+            return;
+        }
+
+        // Letters typed here never reach the move input, see text_input_event
+        if self.menu {
+            match keycode {
+                KeyCode::Return => {
+                    self.menu = false;
+                    self.message = None;
+                }
+                KeyCode::V => self.next_variant(),
+                KeyCode::C => self.next_clock(),
+                KeyCode::A => self.next_computer(),
+                KeyCode::D | KeyCode::Right => self.change_level(1),
+                KeyCode::Left => self.change_level(-1),
+                KeyCode::Escape => {
+                    self.save_window_size();
+                    event::quit(ctx);
+                }
+                _ => (),
+            }
+
+            self.needs_draw = true;
+            return;
+        }
+
+        // Plain letters are typed into the move input, commands need Ctrl
+        if keymods.contains(KeyMods::CTRL) {
+            match keycode {
+                KeyCode::T => self.next_theme(),
+                KeyCode::F => self.flip_board(),
+                KeyCode::V => self.next_variant(),
+                KeyCode::R => self.toggle_chess960(),
+                KeyCode::C => self.next_clock(),
+                KeyCode::A => self.next_computer(),
+                KeyCode::D => self.change_level(1),
+                KeyCode::Z => self.undo(),
+                KeyCode::G => self.resign(),
+                KeyCode::H => self.ask_hint(),
+                KeyCode::E => self.toggle_eval_bar(),
+                KeyCode::P => self.ask_review(),
+                KeyCode::N => self.open_menu(),
+                KeyCode::S => self.save_game(),
+                KeyCode::L => self.load_saved_game(),
+                _ => (),
             }
+            return;
+        }
 
+        match keycode {
+            KeyCode::Left => self.move_cursor(-1, 0),
+            KeyCode::Right => self.move_cursor(1, 0),
+            KeyCode::Up => self.move_cursor(0, -1),
+            KeyCode::Down => self.move_cursor(0, 1),
+            KeyCode::Return if self.input.is_empty() => {
+                if let Some((x, y)) = self.cursor {
+                    self.click_square(x, y);
+                }
+            }
+            KeyCode::Return => self.submit_input(),
+            KeyCode::Back => {
+                self.input.pop();
+                self.needs_draw = true;
+            }
+            KeyCode::Escape => self.cancel(ctx),
             _ => (),
         }
     }
+
+    fn text_input_event(&mut self, _ctx: &mut Context, character: char) {
+        if self.skip_text || self.resume.is_some() || self.menu {
+            self.skip_text = false;
+            return;
+        }
+
+        if character.is_ascii_alphanumeric() || "-=+#@*".contains(character) {
+            self.input.push(character);
+            self.needs_draw = true;
+        }
+    }
+
+    // Don't leave the computer thinking while the window closes
+    fn quit_event(&mut self, _ctx: &mut Context) -> bool {
+        self.cancel_thinking();
+        self.cancel_review();
+        self.save_window_size();
+        false
+    }
+
+    fn resize_event(&mut self, ctx: &mut Context, width: f32, height: f32) {
+        let coords = graphics::Rect::new(0., 0., width, height);
+        if let Err(e) = graphics::set_screen_coordinates(ctx, coords) {
+            eprintln!("Could not resize: {}", e);
+        }
+
+        self.sq_size = Self::square_size(width, height, &self.state);
+        self.settings.window_width = width;
+        self.settings.window_height = height;
+        self.resize_save = Some(RESIZE_SETTLE);
+        self.needs_draw = true;
+    }
 }
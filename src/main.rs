@@ -1,30 +1,182 @@
 extern crate ggez;
+mod analysis;
+mod assets;
+mod bench;
+mod betza;
+mod book;
+mod eval;
 mod game;
+mod notation;
+mod pieces;
+mod rules;
+mod save;
+mod search;
+mod settings;
+mod skill;
+mod tablebase;
+mod theme;
+mod tt;
+mod uci;
+mod variant;
+mod xboard;
 
 use ggez::{
     conf::{WindowMode, WindowSetup},
     event, ContextBuilder, GameResult,
 };
 
+use std::io::{self, BufRead};
 use std::path;
 
+use settings::Settings;
+
 fn main() -> GameResult {
-    let win_mode = WindowMode::default().dimensions(1000., 800.);
+    // Custom variants have to be known before the saved one is read back
+    if let Some(dirs) = settings::project_dirs() {
+        variant::load_custom_variants(&dirs.config_dir().join("variants.toml"));
+        eval::load_weights(&dirs.config_dir().join("eval.toml"));
+    }
 
-    let win_setup = WindowSetup::default().title("King of the hill & three check mod");
+    let mut settings = Settings::load();
+    let mut changed = false;
+    let mut load: Option<path::PathBuf> = None;
+    let mut chess960: Option<Option<u16>> = None;
+    let mut engine = false;
+    let mut build_book: Option<(path::PathBuf, path::PathBuf)> = None;
+    let mut bench_threads: Option<usize> = None;
+
+    let mut args = std::env::args().skip(1);
+    while let Some(arg) = args.next() {
+        match arg.as_str() {
+            "--pieces" => match args.next() {
+                Some(dir) => {
+                    settings.pieces = Some(path::PathBuf::from(dir));
+                    changed = true;
+                }
+                None => eprintln!("--pieces expects a directory"),
+            },
+            "--theme" => match args.next() {
+                Some(name) => {
+                    settings.theme = name;
+                    changed = true;
+                }
+                None => eprintln!("--theme expects a theme name or a TOML file"),
+            },
+            // Position number 0-959, or "random"
+            "--chess960" => match args.next().as_deref() {
+                Some("random") => chess960 = Some(None),
+                Some(n) => match n.parse::<u16>() {
+                    Ok(index) if index < 960 => chess960 = Some(Some(index)),
+                    _ => eprintln!("--chess960 expects a number from 0 to 959 or \"random\""),
+                },
+                None => eprintln!("--chess960 expects a number from 0 to 959 or \"random\""),
+            },
+            "--load" => match args.next() {
+                Some(file) => load = Some(path::PathBuf::from(file)),
+                None => eprintln!("--load expects a saved game file"),
+            },
+            // Syzygy tablebase directory, "" to stop using them
+            "--syzygy" => match args.next() {
+                Some(dir) => {
+                    settings.syzygy_path = Some(path::PathBuf::from(dir)).filter(|dir| !dir.as_os_str().is_empty());
+                    changed = true;
+                }
+                None => eprintln!("--syzygy expects a directory of tablebase files"),
+            },
+            // Talk UCI on stdin/stdout instead of opening a window
+            "--engine" => engine = true,
+            // Turn a PGN file of games into a Polyglot opening book
+            "--build-book" => match (args.next(), args.next()) {
+                (Some(pgn), Some(bin)) => build_book = Some((path::PathBuf::from(pgn), path::PathBuf::from(bin))),
+                _ => eprintln!("--build-book expects a PGN file and the book file to write"),
+            },
+            // Speed of the search with up to this many threads
+            "--bench" => match args.next().and_then(|n| n.parse::<usize>().ok()) {
+                Some(threads) if (1..=search::MAX_THREADS).contains(&threads) => bench_threads = Some(threads),
+                _ => eprintln!("--bench expects a number of threads from 1 to {}", search::MAX_THREADS),
+            },
+            _ => eprintln!("Unknown argument: {}", arg),
+        }
+    }
+
+    if let Some(dir) = &settings.syzygy_path {
+        if let Err(e) = tablebase::init(dir) {
+            eprintln!("Could not read the tablebases: {}", e);
+        }
+    }
+
+    if let Some((pgn, bin)) = build_book {
+        let result = std::fs::read_to_string(&pgn)
+            .map_err(|e| format!("{}: {}", pgn.display(), e))
+            .and_then(|text| book::Book::from_pgn(&text))
+            .and_then(|(book, games)| book.write(&bin).map(|_| (book.len(), games)));
+
+        match result {
+            Ok((moves, games)) => println!("Wrote {} book moves from {} games to {}", moves, games, bin.display()),
+            Err(e) => eprintln!("Could not build the book: {}", e),
+        }
+        return Ok(());
+    }
+
+    if let Some(threads) = bench_threads {
+        bench::run(threads);
+        return Ok(());
+    }
+
+    if engine {
+        run_engine(settings.hash_mb);
+        return Ok(());
+    }
 
-    let mut asset_path = path::PathBuf::from(std::env::var("CARGO_MANIFEST_DIR").unwrap());
-    asset_path.push("src");
-    asset_path.push("assets");
+    if changed {
+        settings.save();
+    }
+
+    let win_mode = WindowMode::default()
+        .dimensions(settings.window_width, settings.window_height)
+        .min_dimensions(400., 300.)
+        .resizable(true);
+
+    let win_setup = WindowSetup::default().title("King of the hill & three check mod");
 
     let (mut ctx, event_loop) = ContextBuilder::new("King of the hill & three check mod", "rejnhed")
         .window_setup(win_setup)
         .window_mode(win_mode)
-        .add_resource_path(asset_path)
         .build()
         .unwrap();
         
-    let game = game::RChess::new(&mut ctx)?;
+    let mut game = game::RChess::new(&mut ctx, settings)?;
+
+    if let Some(index) = chess960 {
+        game.start_chess960(index);
+    }
+
+    if let Some(file) = load {
+        game.load_game_file(&file);
+    }
 
     event::run(ctx, event_loop, game)
 }
+
+// The first command says which protocol the GUI speaks: "xboard" for CECP,
+// anything else is taken as UCI
+fn run_engine(hash_mb: usize) {
+    let mut first = String::new();
+    while first.trim().is_empty() {
+        first.clear();
+        match io::stdin().lock().read_line(&mut first) {
+            Ok(0) | Err(_) => return,
+            Ok(_) => (),
+        }
+    }
+
+    match first.trim() {
+        "xboard" => xboard::Xboard::new(hash_mb).run(),
+        line => {
+            let mut uci = uci::Uci::new(hash_mb);
+            if uci.command(line) {
+                uci.run();
+            }
+        }
+    }
+}
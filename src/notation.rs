@@ -0,0 +1,148 @@
+use crate::rules::{self, square_name, BoardState, Move, Player};
+use crate::variant::Kind;
+
+// Standard algebraic notation for a legal move, e.g. "Nbd2", "exd5", "e8=Q+"
+pub fn to_san(mv: Move, state: &BoardState) -> String {
+    let mut san = san_without_suffix(mv, state);
+
+    let mut after = state.clone();
+    after.make_move(mv);
+
+    let checked = match after.player {
+        Player::White => after.w_check,
+        Player::Black => after.b_check,
+    };
+
+    // A king blown up in Atomic counts as mate
+    if after.variant.kind == Kind::Atomic && after.king(after.player).is_none() {
+        san.push('#');
+    } else if checked {
+        san.push(if rules::legal_moves(&after).is_empty() { '#' } else { '+' });
+    }
+
+    san
+}
+
+fn san_without_suffix(mv: Move, state: &BoardState) -> String {
+    if let Some(pc) = mv.drop {
+        return format!("{}@{}", pc, square_name(mv.to, state.height));
+    }
+
+    let ch = state.board[mv.from.1 as usize][mv.from.0 as usize];
+    let piece = ch.to_ascii_uppercase();
+    let capture = rules::is_piece(state.board[mv.to.1 as usize][mv.to.0 as usize]);
+
+    if rules::castling_destination(mv, state).is_some() {
+        return String::from(if mv.to.0 > mv.from.0 { "O-O" } else { "O-O-O" });
+    }
+
+    let mut san = String::new();
+
+    if piece == 'P' {
+        // Diagonal pawn moves are always captures, en passant included
+        if mv.from.0 != mv.to.0 {
+            san.push((b'a' + mv.from.0) as char);
+            san.push('x');
+        }
+        san.push_str(&square_name(mv.to, state.height));
+
+        if let Some(pc) = mv.promotion {
+            san.push('=');
+            san.push(pc);
+        }
+
+        return san;
+    }
+
+    san.push(piece);
+
+    // Other pieces of the same kind that could also go to the target square
+    let rivals: Vec<(u8, u8)> = rules::legal_moves(state)
+        .into_iter()
+        .filter(|other| {
+            other.to == mv.to
+                && other.from != mv.from
+                && state.board[other.from.1 as usize][other.from.0 as usize] == ch
+        })
+        .map(|other| other.from)
+        .collect();
+
+    if !rivals.is_empty() {
+        let file = (b'a' + mv.from.0) as char;
+        let rank = (state.height - mv.from.1).to_string();
+
+        if rivals.iter().all(|sq| sq.0 != mv.from.0) {
+            san.push(file);
+        } else if rivals.iter().all(|sq| sq.1 != mv.from.1) {
+            san.push_str(&rank);
+        } else {
+            san.push(file);
+            san.push_str(&rank);
+        }
+    }
+
+    if capture {
+        san.push('x');
+    }
+    san.push_str(&square_name(mv.to, state.height));
+
+    san
+}
+
+// Drop the decorations players may or may not type
+fn normalize(text: &str) -> String {
+    let text = text.trim().replace('0', "O");
+
+    text.chars().filter(|c| !"x+#=!?:".contains(*c)).collect()
+}
+
+// Read a move typed by the player, either SAN ("Nf3", "exd5", "O-O") or
+// coordinates ("e2e4", "e7e8q"), and match it against the legal moves.
+// Crazyhouse drops are "N@f3", or just "@e4" for a pawn, the duck is moved
+// with "*@d5" or just "d5"
+pub fn parse_move(text: &str, state: &BoardState) -> Result<Move, String> {
+    let legal = rules::legal_moves(state);
+    let text = text.trim();
+
+    if text.is_empty() {
+        return Err(String::from("Type a move, e.g. Nf3 or e2e4"));
+    }
+
+    // Just the square is enough for the duck
+    if let (true, Some(sq)) = (state.duck_to_move, rules::parse_square(text, state.height)) {
+        return rules::resolve_move(Move::new_duck(sq), state).ok_or_else(|| format!("Illegal move: {}", text));
+    }
+
+    if let Some(mv) = Move::parse(&text.to_ascii_lowercase(), state.height) {
+        // "e7e8" without a piece means a queen
+        let mv = match mv.promotion {
+            None if legal.iter().any(|m| m.from == mv.from && m.to == mv.to && m.promotion.is_some()) => {
+                Move { promotion: Some('Q'), ..mv }
+            }
+            _ => mv,
+        };
+
+        return rules::resolve_move(mv, state).ok_or_else(|| format!("Illegal move: {}", text));
+    }
+
+    let mut wanted = normalize(text);
+
+    // Piece letters are uppercase, but accept "nf3" where it can't mean a pawn
+    if let Some(first) = wanted.chars().next() {
+        if "nrqk".contains(first) {
+            wanted = first.to_ascii_uppercase().to_string() + &wanted[1..];
+        }
+    }
+
+    let matches: Vec<Move> = legal
+        .iter()
+        .filter(|mv| normalize(&san_without_suffix(**mv, state)) == wanted)
+        .cloned()
+        .collect();
+
+    match matches.len() {
+        1 => Ok(matches[0]),
+        0 => Err(format!("Illegal move: {}", text)),
+        _ => Err(format!("Ambiguous move: {}", text)),
+    }
+}
@@ -0,0 +1,43 @@
+use std::sync::OnceLock;
+
+use crate::betza::{self, Step};
+
+// Every piece the game knows and how it moves, in Betza notation. Pawn double
+// steps, en passant and castling don't fit the notation and are added on top
+// in rules.rs
+pub const PIECES: [(char, &str, &str); 10] = [
+    ('P', "Pawn", "fmWfcF"),
+    ('N', "Knight", "N"),
+    ('B', "Bishop", "B"),
+    ('R', "Rook", "R"),
+    ('Q', "Queen", "Q"),
+    ('K', "King", "K"),
+    ('A', "Archbishop", "BN"),
+    ('C', "Chancellor", "RN"),
+    ('M', "Amazon", "QN"),
+    ('L', "Camel", "C"),
+];
+
+static STEPS: OnceLock<Vec<(char, Vec<Step>)>> = OnceLock::new();
+
+// Moves of a built-in piece of either colour, parsed on first use
+pub fn steps(pc: char) -> Option<&'static [Step]> {
+    let steps = STEPS.get_or_init(|| {
+        PIECES
+            .iter()
+            .map(|(pc, _, moves)| (*pc, betza::parse(moves).expect("built-in piece definition")))
+            .collect()
+    });
+
+    let pc = pc.to_ascii_uppercase();
+    steps.iter().find(|(p, _)| *p == pc).map(|(_, steps)| steps.as_slice())
+}
+
+pub fn is_builtin(pc: char) -> bool {
+    PIECES.iter().any(|(p, _, _)| *p == pc.to_ascii_uppercase())
+}
+
+// "KQRBNP..." for messages
+pub fn builtin_letters() -> String {
+    PIECES.iter().map(|(p, _, _)| *p).collect()
+}
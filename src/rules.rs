@@ -0,0 +1,1565 @@
+use std::fmt;
+
+use serde::{Deserialize, Serialize};
+
+use crate::betza::{Mode, Step};
+use crate::pieces;
+use crate::variant::{Kind, Variant};
+
+pub const START_FEN: &str = "rnbqkbnr/pppppppp/8/8/8/8/PPPPPPPP/RNBQKBNR w KQkq - 0 1";
+
+pub const RACING_KINGS_FEN: &str = "8/8/8/8/8/8/krbnNBRK/qrbnNBRQ w - - 0 1";
+
+pub const HORDE_FEN: &str = "rnbqkbnr/pppppppp/8/1PP2PP1/PPPPPPPP/PPPPPPPP/PPPPPPPP/PPPPPPPP w kq - 0 1";
+
+pub const CAPABLANCA_FEN: &str = "rnabqkbcnr/pppppppppp/10/10/10/10/PPPPPPPPPP/RNABQKBCNR w KQkq - 0 1";
+
+pub const LOS_ALAMOS_FEN: &str = "rnqknr/pppppp/6/6/PPPPPP/RNQKNR w - - 0 1";
+
+pub const GARDNER_FEN: &str = "rnbqk/ppppp/5/PPPPP/RNBQK w - - 0 1";
+
+// Widest and tallest board the game supports, smaller boards use the top
+// left corner of the arrays
+pub const MAX_SIZE: usize = 10;
+
+pub const PROMOTIONS: [char; 4] = ['Q', 'R', 'B', 'N'];
+
+pub const CAPABLANCA_PROMOTIONS: [char; 6] = ['Q', 'C', 'A', 'R', 'B', 'N'];
+
+// Los Alamos has no bishops
+pub const LOS_ALAMOS_PROMOTIONS: [char; 3] = ['Q', 'R', 'N'];
+
+// In Antichess the king is an ordinary piece, so pawns may become one too
+pub const ANTICHESS_PROMOTIONS: [char; 5] = ['Q', 'R', 'B', 'N', 'K'];
+
+// Neutral Duck chess blocker, nobody can capture or move through it
+pub const DUCK: char = '*';
+
+// Pieces that can sit in a Crazyhouse pocket, in the order they are shown
+pub const POCKET_PIECES: [char; 5] = ['P', 'N', 'B', 'R', 'Q'];
+
+// King position once it is gone, e.g. blown up in Atomic
+pub const NO_KING: (u8, u8) = (u8::MAX, u8::MAX);
+
+// Indexes into `BoardState::castling`
+pub const WHITE_KING_SIDE: usize = 0;
+pub const WHITE_QUEEN_SIDE: usize = 1;
+pub const BLACK_KING_SIDE: usize = 2;
+pub const BLACK_QUEEN_SIDE: usize = 3;
+
+// Empty squares (among the five left) taking the knights, by Scharnagl number
+const KNIGHTS_960: [(usize, usize); 10] = [
+    (0, 1), (0, 2), (0, 3), (0, 4), (1, 2),
+    (1, 3), (1, 4), (2, 3), (2, 4), (3, 4),
+];
+
+// How a finished game ended
+#[derive(Clone, Copy, PartialEq, Debug)]
+pub enum Outcome {
+    Win(Player),
+    Draw,
+}
+
+#[derive(Clone, Copy, PartialEq, Debug, Serialize, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum Player {
+    White,
+    Black,
+}
+
+impl Player {
+    pub fn switch(&self) -> Self {
+        match *self {
+            Self::White => Self::Black,
+            Self::Black => Self::White,
+        }
+    }
+}
+
+pub struct Point<T>
+where
+    T: Copy,
+{
+    pub x: T,
+    pub y: T,
+}
+
+impl<T> Point<T>
+where
+    T: Copy,
+{
+    pub fn new(x: T, y: T) -> Self {
+        Self { x, y }
+    }
+}
+
+impl<T> std::clone::Clone for Point<T>
+where
+    T: Copy,
+{
+    fn clone(&self) -> Self {
+        Self {
+            x: self.x,
+            y: self.y,
+        }
+    }
+}
+
+// A move in board coordinates, (0, 0) is the top left square, a8 on a normal
+// board
+#[derive(Clone, Copy, PartialEq, Debug)]
+pub struct Move {
+    pub from: (u8, u8),
+    pub to: (u8, u8),
+    // Piece a pawn turns into, always the uppercase letter
+    pub promotion: Option<char>,
+    // Crazyhouse piece put on `to` from the pocket, uppercase, or the duck
+    // being placed in Duck chess, `from` is `to`
+    pub drop: Option<char>,
+}
+
+impl Move {
+    pub fn new(from: (u8, u8), to: (u8, u8)) -> Self {
+        Self { from, to, promotion: None, drop: None }
+    }
+
+    pub fn new_duck(to: (u8, u8)) -> Self {
+        Self { from: to, to, promotion: None, drop: Some(DUCK) }
+    }
+
+    pub fn new_drop(pc: char, to: (u8, u8)) -> Self {
+        Self { from: to, to, promotion: None, drop: Some(pc.to_ascii_uppercase()) }
+    }
+
+    // Coordinate notation on a board `height` ranks tall, e.g. "e2e4",
+    // "e7e8q" or "a10a9", drops are written "N@f3" and duck moves "*@d5"
+    pub fn parse(s: &str, height: u8) -> Option<Move> {
+        if let Some((pc, sq)) = s.split_once('@') {
+            let pc = pc.chars().next().unwrap_or('P').to_ascii_uppercase();
+            if pc == DUCK {
+                return Some(Move::new_duck(parse_square(sq, height)?));
+            }
+            if pocket_index(pc).is_none() {
+                return None;
+            }
+            return Some(Move::new_drop(pc, parse_square(sq, height)?));
+        }
+
+        if !s.is_ascii() || s.len() < 4 {
+            return None;
+        }
+
+        // Each square is a file letter and one or two rank digits
+        let split = 1 + s[1..].find(|c: char| !c.is_ascii_digit())?;
+        let end = split + 1 + s[split + 1..].find(|c: char| !c.is_ascii_digit()).unwrap_or(s.len() - split - 1);
+
+        let mut mv = Move::new(parse_square(&s[..split], height)?, parse_square(&s[split..end], height)?);
+
+        let mut rest = s[end..].chars();
+        if let Some(pc) = rest.next() {
+            let pc = pc.to_ascii_uppercase();
+            if rest.next().is_some() || pc == 'P' || !pieces::is_builtin(pc) {
+                return None;
+            }
+            mv.promotion = Some(pc);
+        }
+
+        Some(mv)
+    }
+
+    // Coordinate notation for `parse`
+    pub fn to_text(&self, height: u8) -> String {
+        if let Some(pc) = self.drop {
+            return format!("{}@{}", pc, square_name(self.to, height));
+        }
+
+        let mut text = format!("{}{}", square_name(self.from, height), square_name(self.to, height));
+
+        if let Some(pc) = self.promotion {
+            text.push(pc.to_ascii_lowercase());
+        }
+
+        text
+    }
+}
+
+pub fn square_name(sq: (u8, u8), height: u8) -> String {
+    format!("{}{}", (b'a' + sq.0) as char, height - sq.1)
+}
+
+pub fn pocket_index(pc: char) -> Option<usize> {
+    POCKET_PIECES.iter().position(|p| *p == pc.to_ascii_uppercase())
+}
+
+fn pocket_side(plyr: Player) -> usize {
+    match plyr {
+        Player::White => 0,
+        Player::Black => 1,
+    }
+}
+
+pub fn square_bit(sq: (u8, u8)) -> u128 {
+    1 << (sq.1 as usize * MAX_SIZE + sq.0 as usize)
+}
+
+pub fn parse_square(s: &str, height: u8) -> Option<(u8, u8)> {
+    let mut chars = s.chars();
+    let file = chars.next()?;
+    let rank: u8 = chars.as_str().parse().ok()?;
+
+    if !file.is_ascii_lowercase() || file as usize - 'a' as usize >= MAX_SIZE || !(1..=height).contains(&rank) {
+        return None;
+    }
+
+    Some((file as u8 - b'a', height - rank))
+}
+
+pub struct BoardState {
+    pub board: [[char; MAX_SIZE]; MAX_SIZE],
+    // Files and ranks in use, counted from the top left
+    pub width: u8,
+    pub height: u8,
+    pub player: Player,
+    pub wk_pos: (u8, u8),
+    pub bk_pos: (u8, u8),
+    // Files a pawn can be taken en passant on, bit 0 being the a-file
+    pub enp_b: u16,
+    pub enp_w: u16,
+    // Starting file of each rook that may still castle
+    pub castling: [Option<u8>; 4],
+    pub b_check: bool,
+    pub w_check: bool,
+    pub b_checks: u8,
+    pub w_checks: u8,
+    pub w_win: bool,
+    pub b_win: bool,
+    pub draw: bool,
+    pub variant: Variant,
+    pub halfmove: u16,
+    pub fullmove: u16,
+    // Crazyhouse pieces ready to drop, counted per POCKET_PIECES, white first
+    pub pockets: [[u8; 5]; 2],
+    // Squares holding promoted pieces, these go back to pawns when captured
+    pub promoted: u128,
+    // Duck chess: the piece has moved, the same side now has to move the duck
+    pub duck_to_move: bool,
+}
+
+impl std::clone::Clone for BoardState {
+    fn clone(&self) -> Self {
+        Self {
+            board: self.board,
+            width: self.width,
+            height: self.height,
+            player: self.player,
+            wk_pos: self.wk_pos,
+            bk_pos: self.bk_pos,
+            enp_b: self.enp_b,
+            enp_w: self.enp_w,
+            castling: self.castling,
+            b_check: self.b_check,
+            w_check: self.w_check,
+            b_checks: self.b_checks,
+            w_checks: self.w_checks,
+            w_win: self.w_win,
+            b_win: self.b_win,
+            draw: self.draw,
+            variant: self.variant,
+            halfmove: self.halfmove,
+            fullmove: self.fullmove,
+            pockets: self.pockets,
+            promoted: self.promoted,
+            duck_to_move: self.duck_to_move,
+        }
+    }
+}
+
+impl fmt::Display for BoardState {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        let mut out = String::from("[\n");
+
+        for row in self.board.iter().take(self.height as usize) {
+            out.push_str(&format!("\t{:?}\n", &row[..self.width as usize]));
+        }
+
+        out.push_str("]");
+
+        write!(f, "{}", out)
+    }
+}
+
+impl BoardState {
+    pub fn new(variant: Variant) -> Self {
+        Self::from_fen(start_fen(variant), variant).unwrap()
+    }
+
+    // Chess960 starting position by its number, 518 is the normal setup
+    pub fn chess960(index: u16, variant: Variant) -> Self {
+        Self::from_fen(&chess960_fen(index), variant).unwrap()
+    }
+
+    // Parse a FEN, three-check positions may carry a trailing "+W+B" field
+    // with the number of checks given by each side and Crazyhouse ones a
+    // "[...]" pocket after the board with "~" marking promoted pieces
+    pub fn from_fen(fen: &str, variant: Variant) -> Result<Self, String> {
+        let fields: Vec<&str> = fen.split_whitespace().collect();
+
+        if fields.len() < 4 {
+            return Err(format!("incomplete FEN \"{}\"", fen));
+        }
+
+        let mut state = Self {
+            board: [['-'; MAX_SIZE]; MAX_SIZE],
+            width: 0,
+            height: 0,
+            player: Player::White,
+            wk_pos: NO_KING,
+            bk_pos: NO_KING,
+            enp_b: 0,
+            enp_w: 0,
+            castling: [None; 4],
+            b_check: false,
+            w_check: false,
+            b_checks: 0,
+            w_checks: 0,
+            w_win: false,
+            b_win: false,
+            draw: false,
+            variant,
+            halfmove: 0,
+            fullmove: 1,
+            pockets: [[0; 5]; 2],
+            promoted: 0,
+            duck_to_move: false,
+        };
+
+        let (placement, pocket) = match fields[0].split_once('[') {
+            Some((placement, pocket)) => (placement, pocket.trim_end_matches(']')),
+            None => (fields[0], ""),
+        };
+
+        for ch in pocket.chars() {
+            let idx = pocket_index(ch).ok_or(format!("unknown pocket piece '{}'", ch))?;
+            let side = if ch.is_ascii_uppercase() { 0 } else { 1 };
+            state.pockets[side][idx] += 1;
+        }
+
+        // The board is as big as the placement, every rank as wide as the first
+        let ranks: Vec<&str> = placement.split('/').collect();
+        if !(3..=MAX_SIZE).contains(&ranks.len()) {
+            return Err(format!("expected 3 to {} ranks in \"{}\"", MAX_SIZE, placement));
+        }
+        state.height = ranks.len() as u8;
+
+        for (y, rank) in ranks.iter().enumerate() {
+            let mut x = 0;
+            let mut chars = rank.chars().peekable();
+            // Nothing may land past the board, however the rank is written
+            let limit = match y {
+                0 => MAX_SIZE,
+                _ => state.width as usize,
+            };
+            let too_wide = || format!("rank \"{}\" is more than {} squares wide", rank, limit);
+
+            while let Some(ch) = chars.next() {
+                if ch == '~' && x > 0 {
+                    if x > limit {
+                        return Err(too_wide());
+                    }
+                    state.promoted |= square_bit((x as u8 - 1, y as u8));
+                    continue;
+                }
+
+                // Runs of empty squares can take two digits on wide boards
+                if let Some(mut n) = ch.to_digit(10) {
+                    if let Some(m) = chars.peek().and_then(|c| c.to_digit(10)) {
+                        n = n * 10 + m;
+                        chars.next();
+                    }
+                    x += n as usize;
+                    if x > limit {
+                        return Err(too_wide());
+                    }
+                    continue;
+                }
+
+                if !known_piece(ch, variant) && ch != DUCK {
+                    return Err(format!("unknown piece '{}'", ch));
+                }
+                if x >= limit {
+                    return Err(too_wide());
+                }
+
+                match ch {
+                    'K' => state.wk_pos = (x as u8, y as u8),
+                    'k' => state.bk_pos = (x as u8, y as u8),
+                    _ => (),
+                }
+
+                state.board[y][x] = ch;
+                x += 1;
+            }
+
+            if y == 0 {
+                if !(3..=MAX_SIZE).contains(&x) {
+                    return Err(format!("rank \"{}\" is not 3 to {} squares wide", rank, MAX_SIZE));
+                }
+                state.width = x as u8;
+            }
+
+            if x != state.width as usize {
+                return Err(format!("rank \"{}\" is not {} squares wide", rank, state.width));
+            }
+        }
+
+        // Antichess kings can be captured and the Horde has none
+        let (white_king, black_king) = match variant.kind {
+            Kind::Antichess => (false, false),
+            Kind::Horde => (false, true),
+            _ => (true, true),
+        };
+        if (white_king && state.wk_pos == NO_KING) || (black_king && state.bk_pos == NO_KING) {
+            return Err(String::from("missing king"));
+        }
+
+        state.player = match fields[1] {
+            "w" => Player::White,
+            "b" => Player::Black,
+            s => return Err(format!("unknown side to move \"{}\"", s)),
+        };
+
+        // KQkq pick the outermost rook, X-FEN/Shredder-FEN name the rook's file.
+        // Antichess has no castling whatever the FEN says
+        let castling = if variant.kind == Kind::Antichess { "" } else { fields[2] };
+        for ch in castling.chars().filter(|ch| *ch != '-') {
+            let bad = || format!("bad castling field \"{}\"", fields[2]);
+
+            let white = ch.is_ascii_uppercase();
+            let (king, back) = if white { (state.wk_pos, state.height - 1) } else { (state.bk_pos, 0) };
+
+            if king.1 != back {
+                return Err(bad());
+            }
+
+            let row = &state.board[back as usize];
+            let rook = |x: &u8| castles_with(row[*x as usize], white, variant);
+            let file = match ch.to_ascii_lowercase() {
+                'k' => (king.0 + 1..state.width).rev().find(rook),
+                'q' => (0..king.0).find(rook),
+                f @ 'a'..='j' => Some(f as u8 - b'a').filter(|x| *x < state.width).filter(rook),
+                _ => None,
+            }
+            .ok_or_else(bad)?;
+
+            let idx = castling_index(ch.is_ascii_uppercase(), file > king.0);
+            state.castling[idx] = Some(file);
+        }
+
+        if fields[3] != "-" {
+            let (x, _) = parse_square(fields[3], state.height).ok_or(format!("bad en passant square \"{}\"", fields[3]))?;
+            match state.player {
+                Player::White => state.enp_b = 1 << x,
+                Player::Black => state.enp_w = 1 << x,
+            }
+        }
+
+        let mut counters = 0;
+        for field in fields[4..].iter() {
+            if let Some(checks) = field.strip_prefix('+') {
+                let mut counts = checks.split('+').map(|n| n.parse::<u8>());
+                match (counts.next(), counts.next()) {
+                    (Some(Ok(w)), Some(Ok(b))) => {
+                        state.w_checks = w;
+                        state.b_checks = b;
+                    }
+                    _ => return Err(format!("bad check counter \"{}\"", field)),
+                }
+                continue;
+            }
+
+            let n = field.parse::<u16>().map_err(|_| format!("bad move counter \"{}\"", field))?;
+            match counters {
+                0 => state.halfmove = n,
+                _ => state.fullmove = n.max(1),
+            }
+            counters += 1;
+        }
+
+        state.w_check = check_for_checks(Player::White, &mut state);
+        state.b_check = check_for_checks(Player::Black, &mut state);
+
+        Ok(state)
+    }
+
+    pub fn to_fen(&self) -> String {
+        let mut fen = String::new();
+        let crazyhouse = self.variant.kind == Kind::Crazyhouse;
+
+        for (y, row) in self.board.iter().take(self.height as usize).enumerate() {
+            let mut empty = 0;
+
+            for (x, ch) in row.iter().take(self.width as usize).enumerate() {
+                if is_piece(*ch) {
+                    if empty > 0 {
+                        fen.push_str(&empty.to_string());
+                        empty = 0;
+                    }
+                    fen.push(*ch);
+                    if crazyhouse && self.promoted & square_bit((x as u8, y as u8)) > 0 {
+                        fen.push('~');
+                    }
+                } else {
+                    empty += 1;
+                }
+            }
+
+            if empty > 0 {
+                fen.push_str(&empty.to_string());
+            }
+            if y + 1 < self.height as usize {
+                fen.push('/');
+            }
+        }
+
+        if crazyhouse {
+            fen.push('[');
+            for side in 0..2 {
+                for (idx, pc) in POCKET_PIECES.iter().enumerate().rev() {
+                    let pc = if side == 0 { *pc } else { pc.to_ascii_lowercase() };
+                    for _ in 0..self.pockets[side][idx] {
+                        fen.push(pc);
+                    }
+                }
+            }
+            fen.push(']');
+        }
+
+        fen.push_str(match self.player {
+            Player::White => " w ",
+            Player::Black => " b ",
+        });
+
+        // X-FEN: KQkq unless another rook sits further out on that wing
+        let mut castling = String::new();
+        for idx in 0..4 {
+            let file = match self.castling[idx] {
+                Some(file) => file,
+                None => continue,
+            };
+
+            let white = idx == WHITE_KING_SIDE || idx == WHITE_QUEEN_SIDE;
+            let king_side = idx == WHITE_KING_SIDE || idx == BLACK_KING_SIDE;
+            let back = if white { self.height as usize - 1 } else { 0 };
+            let row = &self.board[back];
+            let rook = |x: u8| castles_with(row[x as usize], white, self.variant);
+
+            let outermost = if king_side {
+                (file + 1..self.width).all(|x| !rook(x))
+            } else {
+                (0..file).all(|x| !rook(x))
+            };
+
+            let ch = match (outermost, king_side) {
+                (true, true) => 'k',
+                (true, false) => 'q',
+                (false, _) => (b'a' + file) as char,
+            };
+
+            castling.push(if white { ch.to_ascii_uppercase() } else { ch });
+        }
+        if castling.is_empty() {
+            castling.push('-');
+        }
+        fen.push_str(&castling);
+
+        // The square behind a pawn that just double-stepped
+        let (enp, y) = match self.player {
+            Player::White => (self.enp_b, 2),
+            Player::Black => (self.enp_w, self.height - 3),
+        };
+        if enp > 0 {
+            fen.push_str(&format!(" {}", square_name((enp.trailing_zeros() as u8, y), self.height)));
+        } else {
+            fen.push_str(" -");
+        }
+
+        fen.push_str(&format!(" {} {}", self.halfmove, self.fullmove));
+
+        if checks_to_win(self.variant).is_some() {
+            fen.push_str(&format!(" +{}+{}", self.w_checks, self.b_checks));
+        }
+
+        fen
+    }
+
+    pub fn king(&self, plyr: Player) -> Option<(u8, u8)> {
+        let pos = match plyr {
+            Player::White => self.wk_pos,
+            Player::Black => self.bk_pos,
+        };
+
+        Some(pos).filter(|pos| *pos != NO_KING)
+    }
+
+    // Play a move for the side to move and hand the turn over
+    pub fn make_move(&mut self, mv: Move) {
+        if mv.drop == Some(DUCK) {
+            place_duck(mv.to, self);
+            self.duck_to_move = false;
+            self.player = self.player.switch();
+            return;
+        }
+
+        if let Some(pc) = mv.drop {
+            drop_piece(pc, mv.to, self);
+            self.halfmove += 1;
+            if self.player == Player::Black {
+                self.fullmove += 1;
+            }
+            self.player = self.player.switch();
+            return;
+        }
+
+        let ch = self.board[mv.from.1 as usize][mv.from.0 as usize];
+        // Castling lands on the own rook, that is no capture
+        let capture = is_opponent(self.player, self.board[mv.to.1 as usize][mv.to.0 as usize]);
+
+        move_piece_to(Point::new(mv.from.0, mv.from.1), Point::new(mv.to.0, mv.to.1), mv.promotion, self);
+
+        if capture || ch == 'p' || ch == 'P' {
+            self.halfmove = 0;
+        } else {
+            self.halfmove += 1;
+        }
+
+        if self.player == Player::Black {
+            self.fullmove += 1;
+        }
+
+        // The turn only passes once the duck has moved
+        match self.variant.kind {
+            Kind::Duck => self.duck_to_move = true,
+            _ => self.player = self.player.switch(),
+        }
+    }
+}
+
+// Variants with their own setup don't take Chess960 shuffles
+pub fn start_fen(variant: Variant) -> &'static str {
+    if let Some(custom) = variant.custom() {
+        return custom.start_fen.as_str();
+    }
+
+    match variant.kind {
+        Kind::RacingKings => RACING_KINGS_FEN,
+        Kind::Horde => HORDE_FEN,
+        Kind::Capablanca => CAPABLANCA_FEN,
+        Kind::LosAlamos => LOS_ALAMOS_FEN,
+        Kind::Gardner => GARDNER_FEN,
+        _ => START_FEN,
+    }
+}
+
+// Centre squares a king has to reach in King of the hill, d4 to e5 on a
+// normal board and a single square when both sides are odd
+pub fn hill(width: u8, height: u8) -> Vec<(u8, u8)> {
+    let middle = |n: u8| if n % 2 == 0 { n / 2 - 1..=n / 2 } else { n / 2..=n / 2 };
+
+    middle(height).flat_map(|y| middle(width).map(move |x| (x, y))).collect()
+}
+
+// Squares a king is racing to, drawn like the hill
+pub fn goal_squares(state: &BoardState) -> Vec<(u8, u8)> {
+    let variant = state.variant;
+    let mut squares = match (variant.kind, variant.king_of_the_hill) {
+        (Kind::RacingKings, _) => (0..state.width).map(|x| (x, 0)).collect(),
+        (_, true) => hill(state.width, state.height),
+        (_, false) => Vec::new(),
+    };
+
+    if let Some(custom) = variant.custom() {
+        for sq in custom.goal_squares.iter() {
+            if !squares.contains(sq) {
+                squares.push(*sq);
+            }
+        }
+    }
+
+    squares
+}
+
+// Checks a side has to give to win, three-check or a custom variant's count
+pub fn checks_to_win(variant: Variant) -> Option<u8> {
+    match (variant.custom().and_then(|custom| custom.checks_to_win), variant.three_check) {
+        (Some(n), _) => Some(n),
+        (None, true) => Some(3),
+        (None, false) => None,
+    }
+}
+
+// Whether a king can castle with `pc`, the rook unless a custom variant says otherwise
+pub fn castles_with(pc: char, white: bool, variant: Variant) -> bool {
+    if pc == DUCK || pc.is_ascii_uppercase() != white {
+        return false;
+    }
+
+    let pc = pc.to_ascii_uppercase();
+    match variant.custom() {
+        Some(custom) => pc != 'K' && custom.castling.contains(&pc),
+        None => pc == 'R',
+    }
+}
+
+// Scharnagl numbering: bishops, then queen, then knights, the rest is R K R
+pub fn chess960_fen(index: u16) -> String {
+    let mut rank = ['-'; 8];
+    let n = index as usize % 960;
+
+    let (n, light) = (n / 4, n % 4);
+    rank[light * 2 + 1] = 'b';
+
+    let (n, dark) = (n / 4, n % 4);
+    rank[dark * 2] = 'b';
+
+    let (n, queen) = (n / 6, n % 6);
+    let empty: Vec<usize> = (0..8).filter(|x| rank[*x] == '-').collect();
+    rank[empty[queen]] = 'q';
+
+    let empty: Vec<usize> = (0..8).filter(|x| rank[*x] == '-').collect();
+    let (a, b) = KNIGHTS_960[n];
+    rank[empty[a]] = 'n';
+    rank[empty[b]] = 'n';
+
+    let empty: Vec<usize> = (0..8).filter(|x| rank[*x] == '-').collect();
+    rank[empty[0]] = 'r';
+    rank[empty[1]] = 'k';
+    rank[empty[2]] = 'r';
+
+    let black: String = rank.iter().collect();
+    format!("{}/pppppppp/8/8/8/8/PPPPPPPP/{} w KQkq - 0 1", black, black.to_ascii_uppercase())
+}
+
+pub fn castling_index(white: bool, king_side: bool) -> usize {
+    match (white, king_side) {
+        (true, true) => WHITE_KING_SIDE,
+        (true, false) => WHITE_QUEEN_SIDE,
+        (false, true) => BLACK_KING_SIDE,
+        (false, false) => BLACK_QUEEN_SIDE,
+    }
+}
+
+// Castling is encoded as the king moving onto its own rook, this gives the
+// square the king actually ends up on
+pub fn castling_destination(mv: Move, state: &BoardState) -> Option<(u8, u8)> {
+    let king = state.board[mv.from.1 as usize][mv.from.0 as usize];
+    let target = state.board[mv.to.1 as usize][mv.to.0 as usize];
+
+    let white = king == 'K';
+    match king {
+        'K' | 'k' if mv.from.1 == mv.to.1 && castles_with(target, white, state.variant) => {
+            Some((castled_files(mv.to.0 > mv.from.0, state).0, mv.from.1))
+        }
+        _ => None,
+    }
+}
+
+// Files the king and the rook end up on after castling, the g- and f-file
+// (c and d on the queen side) on a normal board
+pub fn castled_files(king_side: bool, state: &BoardState) -> (u8, u8) {
+    match king_side {
+        true => (state.width - 2, state.width - 3),
+        false => (2, 3),
+    }
+}
+
+// Match a move against the legal ones, also taking the king's destination
+// square for castling (e1g1)
+pub fn resolve_move(mv: Move, state: &BoardState) -> Option<Move> {
+    let legal = legal_moves(state);
+
+    if legal.contains(&mv) {
+        return Some(mv);
+    }
+
+    legal
+        .into_iter()
+        .find(|m| m.from == mv.from && castling_destination(*m, state) == Some(mv.to))
+}
+
+// Squares the piece on `from` can move to without leaving its own king in
+// check, giving check first when a custom variant makes it compulsory
+pub fn legal_targets(from: (u8, u8), state: &BoardState) -> Vec<(u8, u8)> {
+    let targets = safe_targets(from, state);
+
+    if !state.variant.custom().map_or(false, |custom| custom.compulsory_checks) || !check_available(state) {
+        return targets;
+    }
+
+    targets.into_iter().filter(|to| gives_check(from, *to, state)).collect()
+}
+
+fn safe_targets(from: (u8, u8), state: &BoardState) -> Vec<(u8, u8)> {
+    let ch = state.board[from.1 as usize][from.0 as usize];
+
+    if !is_piece(ch) || is_opponent(state.player, ch) || state.duck_to_move {
+        return Vec::new();
+    }
+
+    // Antichess: taking is compulsory whenever any piece can
+    let compulsory = state.variant.kind == Kind::Antichess
+        || state.variant.custom().map_or(false, |custom| custom.compulsory_captures);
+    let must_capture = compulsory && capture_available(state);
+
+    get_piece_moves(ch, Point::new(from.0, from.1), state)
+        .into_iter()
+        .filter(|to| !must_capture || is_capture(from, *to, state))
+        .filter(|to| {
+            let mut state_ = state.clone();
+            move_piece_to(Point::new(from.0, from.1), Point::new(to.0, to.1), None, &mut state_);
+            king_safe(state.player, &state_)
+        })
+        .collect()
+}
+
+fn gives_check(from: (u8, u8), to: (u8, u8), state: &BoardState) -> bool {
+    let mut state_ = state.clone();
+    move_piece_to(Point::new(from.0, from.1), Point::new(to.0, to.1), None, &mut state_);
+
+    match state.player {
+        Player::White => state_.b_check,
+        Player::Black => state_.w_check,
+    }
+}
+
+fn check_available(state: &BoardState) -> bool {
+    squares(state).any(|sq| safe_targets(sq, state).into_iter().any(|to| gives_check(sq, to, state)))
+}
+
+// Whether the side that just moved still has its king out of check, in
+// Atomic blowing up the other king wins on the spot, check or not
+fn king_safe(plyr: Player, after: &BoardState) -> bool {
+    match after.variant.kind {
+        Kind::Antichess | Kind::Duck => return true,
+        // Racing Kings: neither side may be in check, giving one included
+        Kind::RacingKings => return !after.w_check && !after.b_check,
+        Kind::Atomic => {
+            if after.king(plyr).is_none() {
+                return false;
+            }
+
+            if after.king(plyr.switch()).is_none() {
+                return true;
+            }
+        }
+        _ => (),
+    }
+
+    match plyr {
+        Player::White => !after.w_check,
+        Player::Black => !after.b_check,
+    }
+}
+
+pub fn is_capture(from: (u8, u8), to: (u8, u8), state: &BoardState) -> bool {
+    let ch = state.board[from.1 as usize][from.0 as usize];
+
+    is_opponent(state.player, state.board[to.1 as usize][to.0 as usize])
+        || ((ch == 'p' || ch == 'P') && from.0 != to.0)
+}
+
+fn capture_available(state: &BoardState) -> bool {
+    squares(state).any(|(x, y)| {
+        let ch = state.board[y as usize][x as usize];
+
+        is_piece(ch)
+            && !is_opponent(state.player, ch)
+            && get_piece_moves(ch, Point::new(x, y), state)
+                .into_iter()
+                .any(|to| is_capture((x, y), to, state))
+    })
+}
+
+// Every square of the board, rank by rank from the top
+pub fn squares(state: &BoardState) -> impl Iterator<Item = (u8, u8)> {
+    let (width, height) = (state.width, state.height);
+
+    (0..height).flat_map(move |y| (0..width).map(move |x| (x, y)))
+}
+
+// Pieces pawns can promote to, the king too in Antichess
+pub fn promotions(state: &BoardState) -> &'static [char] {
+    match state.variant.kind {
+        Kind::Antichess => &ANTICHESS_PROMOTIONS[..],
+        Kind::Capablanca => &CAPABLANCA_PROMOTIONS[..],
+        Kind::LosAlamos => &LOS_ALAMOS_PROMOTIONS[..],
+        _ => &PROMOTIONS[..],
+    }
+}
+
+// Whether a pawn standing on `to` has reached its last rank
+pub fn promotes(ch: char, to: (u8, u8), state: &BoardState) -> bool {
+    (ch == 'P' && to.1 == 0) || (ch == 'p' && to.1 == state.height - 1)
+}
+
+// Every legal move for the side to move, pawns reaching the last rank get
+// one move per promotion piece
+pub fn legal_moves(state: &BoardState) -> Vec<Move> {
+    let mut moves = Vec::new();
+
+    for (x, y) in squares(state) {
+        let ch = state.board[y as usize][x as usize];
+
+        for to in legal_targets((x, y), state) {
+            if promotes(ch, to, state) {
+                for pc in promotions(state).iter() {
+                    moves.push(Move { promotion: Some(*pc), ..Move::new((x, y), to) });
+                }
+            } else {
+                moves.push(Move::new((x, y), to));
+            }
+        }
+    }
+
+    moves.append(&mut legal_drops(state));
+
+    // The duck has to go to another empty square
+    if state.duck_to_move {
+        for (x, y) in squares(state) {
+            if !is_piece(state.board[y as usize][x as usize]) {
+                moves.push(Move::new_duck((x, y)));
+            }
+        }
+    }
+
+    moves
+}
+
+// Crazyhouse drops for the side to move, pawns never go on the first or
+// last rank
+pub fn legal_drops(state: &BoardState) -> Vec<Move> {
+    let mut moves = Vec::new();
+    let side = pocket_side(state.player);
+
+    // Without check a drop can only block lines, never open one
+    let checked = match state.player {
+        Player::White => state.w_check,
+        Player::Black => state.b_check,
+    };
+
+    for (idx, pc) in POCKET_PIECES.iter().enumerate() {
+        if state.pockets[side][idx] == 0 {
+            continue;
+        }
+
+        for (x, y) in squares(state) {
+            if is_piece(state.board[y as usize][x as usize]) || (*pc == 'P' && (y == 0 || y == state.height - 1)) {
+                continue;
+            }
+
+            let mv = Move::new_drop(*pc, (x, y));
+
+            if checked {
+                let mut state_ = state.clone();
+                drop_piece(*pc, mv.to, &mut state_);
+                if !king_safe(state.player, &state_) {
+                    continue;
+                }
+            }
+
+            moves.push(mv);
+        }
+    }
+
+    moves
+}
+
+// Fairy pieces follow the same case rule as the standard ones
+pub fn is_white_piece(pc: char) -> bool {
+    pc.is_ascii_uppercase()
+}
+
+pub fn is_black_piece(pc: char) -> bool {
+    pc.is_ascii_lowercase()
+}
+
+// Built-in pieces, plus the fairy ones a custom variant defines
+pub fn known_piece(pc: char, variant: Variant) -> bool {
+    pieces::is_builtin(pc)
+        || variant.custom().map_or(false, |custom| custom.piece(pc).is_some())
+}
+
+pub fn is_opponent(plyr: Player, ch: char) -> bool {
+    match plyr {
+        Player::White => is_black_piece(ch),
+        Player::Black => is_white_piece(ch),
+    }
+}
+
+pub fn is_piece(ch: char) -> bool {
+    ch != '-'
+}
+
+pub fn move_piece_to(from: Point<u8>, to: Point<u8>, promote: Option<char>, state: &mut BoardState) {
+    let x = from.x as usize;
+    let y = from.y as usize;
+
+    let ch = state.board[y][x];
+    let target = state.board[to.y as usize][to.x as usize];
+    // En passant is the only capture landing on an empty square
+    let capture = (is_piece(target) && is_white_piece(ch) != is_white_piece(target))
+        || ((ch == 'p' || ch == 'P') && from.x != to.x);
+
+    // Crazyhouse: the captured piece joins the capturer's pocket, promoted
+    // pieces as pawns again
+    if capture && state.variant.kind == Kind::Crazyhouse {
+        let taken_sq = if is_piece(target) { (to.x, to.y) } else { (to.x, from.y) };
+        let taken = match state.promoted & square_bit(taken_sq) {
+            0 => state.board[taken_sq.1 as usize][taken_sq.0 as usize].to_ascii_uppercase(),
+            _ => 'P',
+        };
+        let side = if is_white_piece(ch) { 0 } else { 1 };
+
+        if let Some(idx) = pocket_index(taken) {
+            state.pockets[side][idx] += 1;
+        }
+    }
+
+    // Kings can be taken in Antichess and Duck chess, a king promoted in
+    // Antichess takes over from the one lost
+    if capture && (target == 'K' || target == 'k') {
+        let white = target == 'K';
+        let other = squares(state)
+            .find(|(x, y)| (*x, *y) != (to.x, to.y) && state.board[*y as usize][*x as usize] == target)
+            .unwrap_or(NO_KING);
+        match white {
+            true => state.wk_pos = other,
+            false => state.bk_pos = other,
+        }
+        state.castling[castling_index(white, true)] = None;
+        state.castling[castling_index(white, false)] = None;
+    }
+
+    let was_promoted = state.promoted & square_bit((from.x, from.y)) > 0;
+    state.promoted &= !(square_bit((from.x, from.y)) | square_bit((to.x, to.y)));
+    if was_promoted || promote.is_some() {
+        state.promoted |= square_bit((to.x, to.y));
+    }
+
+    state.enp_b = 0;
+    state.enp_w = 0;
+
+    // Moving a castling rook, or capturing one, loses that right
+    for idx in 0..4 {
+        if let Some(file) = state.castling[idx] {
+            let back = if idx == WHITE_KING_SIDE || idx == WHITE_QUEEN_SIDE { state.height - 1 } else { 0 };
+            if (from.x, from.y) == (file, back) || (to.x, to.y) == (file, back) {
+                state.castling[idx] = None;
+            }
+        }
+    }
+
+    match ch {
+        'K' | 'k' => {
+            let white = ch == 'K';
+            state.castling[castling_index(white, true)] = None;
+            state.castling[castling_index(white, false)] = None;
+
+            let mut dest = (to.x, to.y);
+
+            if let Some(king_to) = castling_destination(Move::new((from.x, from.y), dest), state) {
+                let rook = state.board[to.y as usize][to.x as usize];
+                let rook_x = castled_files(to.x > from.x, state).1 as usize;
+
+                state.board[y][x] = '-';
+                state.board[to.y as usize][to.x as usize] = '-';
+                state.board[y][rook_x] = rook;
+                state.board[y][king_to.0 as usize] = ch;
+                dest = king_to;
+            }
+
+            match white {
+                true => state.wk_pos = dest,
+                false => state.bk_pos = dest,
+            }
+
+            if dest != (to.x, to.y) {
+                update_checks(state);
+                return;
+            }
+        }
+
+        // A double step from the second rank can be taken en passant, which
+        // is a diagonal move onto an empty square. Horde pawns double-stepping
+        // from the first rank can't
+        'p' | 'P' => {
+            if (to.y as i8 - from.y as i8).abs() == 2 {
+                match ch {
+                    'p' if from.y == 1 => state.enp_b = 1 << from.x,
+                    'P' if from.y == state.height - 2 => state.enp_w = 1 << from.x,
+                    _ => (),
+                }
+            } else if from.x != to.x && !is_piece(target) {
+                state.board[from.y as usize][to.x as usize] = '-';
+            }
+        }
+
+        _ => (),
+    }
+
+    state.board[to.y as usize][to.x as usize] = match promote {
+        Some(pc) if is_white_piece(ch) => pc.to_ascii_uppercase(),
+        Some(pc) => pc.to_ascii_lowercase(),
+        None => ch,
+    };
+    state.board[y][x] = '-';
+
+    // Antichess promotion to a king, which counts once the side has none
+    if promote.map(|pc| pc.to_ascii_uppercase()) == Some('K') {
+        match is_white_piece(ch) {
+            true if state.wk_pos == NO_KING => state.wk_pos = (to.x, to.y),
+            false if state.bk_pos == NO_KING => state.bk_pos = (to.x, to.y),
+            _ => (),
+        }
+    }
+
+    if capture && state.variant.kind == Kind::Atomic {
+        explode((to.x, to.y), state);
+    }
+
+    update_checks(state);
+}
+
+// Atomic: the capturing piece goes up along with every piece but pawns
+// around the capture square
+fn explode(at: (u8, u8), state: &mut BoardState) {
+    state.board[at.1 as usize][at.0 as usize] = '-';
+
+    for dy in -1..=1 {
+        for dx in -1..=1 {
+            let x = at.0 as i8 + dx;
+            let y = at.1 as i8 + dy;
+
+            if !(0..state.width as i8).contains(&x) || !(0..state.height as i8).contains(&y) {
+                continue;
+            }
+
+            let ch = state.board[y as usize][x as usize];
+            if ch != 'p' && ch != 'P' {
+                state.board[y as usize][x as usize] = '-';
+            }
+        }
+    }
+
+    if state.wk_pos != NO_KING && state.board[state.wk_pos.1 as usize][state.wk_pos.0 as usize] != 'K' {
+        state.wk_pos = NO_KING;
+    }
+    if state.bk_pos != NO_KING && state.board[state.bk_pos.1 as usize][state.bk_pos.0 as usize] != 'k' {
+        state.bk_pos = NO_KING;
+    }
+
+    // Castling is gone with the king or with a rook on its starting square
+    for idx in 0..4 {
+        if let Some(file) = state.castling[idx] {
+            let white = idx == WHITE_KING_SIDE || idx == WHITE_QUEEN_SIDE;
+            let back = if white { state.height as usize - 1 } else { 0 };
+            let king = if white { state.wk_pos } else { state.bk_pos };
+            if king == NO_KING || !is_piece(state.board[back][file as usize]) {
+                state.castling[idx] = None;
+            }
+        }
+    }
+}
+
+// Duck chess: move the duck to `to`, off its old square if it had one
+fn place_duck(to: (u8, u8), state: &mut BoardState) {
+    for row in state.board.iter_mut() {
+        for sq in row.iter_mut() {
+            if *sq == DUCK {
+                *sq = '-';
+            }
+        }
+    }
+
+    state.board[to.1 as usize][to.0 as usize] = DUCK;
+    state.enp_b = 0;
+    state.enp_w = 0;
+}
+
+// Crazyhouse: put a piece from the pocket of the side to move on an empty square
+pub fn drop_piece(pc: char, to: (u8, u8), state: &mut BoardState) {
+    if let Some(idx) = pocket_index(pc) {
+        let side = pocket_side(state.player);
+        state.pockets[side][idx] = state.pockets[side][idx].saturating_sub(1);
+    }
+
+    state.board[to.1 as usize][to.0 as usize] = match state.player {
+        Player::White => pc.to_ascii_uppercase(),
+        Player::Black => pc.to_ascii_lowercase(),
+    };
+    state.enp_b = 0;
+    state.enp_w = 0;
+
+    update_checks(state);
+}
+
+fn update_checks(state: &mut BoardState) {
+    state.b_check = check_for_checks(Player::Black, state);
+    state.w_check = check_for_checks(Player::White, state);
+    if state.w_check { state.b_checks = state.b_checks+1; }
+    if state.b_check { state.w_checks = state.w_checks+1; }
+}
+
+// Single steps and captures come from the pawn's definition, double steps and
+// en passant are added here
+fn mv_pawn(pos: Point<u8>, state: &BoardState) -> Vec<(u8, u8)> {
+    let mut moves = mv_betza(pieces::steps('P').unwrap(), pos.clone(), state);
+
+    let last = state.height - 1;
+    let white = is_white_piece(state.board[pos.y as usize][pos.x as usize]);
+    let (dir, first, enp_rank, enp) = match white {
+        true => (-1, last - 1, 3, state.enp_b),
+        false => (1, 1, last - 3, state.enp_w),
+    };
+
+    let y_1 = pos.y as i8 + dir;
+    if y_1 < 0 || y_1 >= state.height as i8 {
+        return moves;
+    }
+
+    // Horde pawns may also double-step from the first rank, minichess pawns
+    // never double-step
+    let first_step = state.height >= 8
+        && (pos.y == first || (white && pos.y == last && state.variant.kind == Kind::Horde));
+    let y_2 = y_1 + dir;
+
+    if first_step
+        && !is_piece(state.board[y_1 as usize][pos.x as usize])
+        && !is_piece(state.board[y_2 as usize][pos.x as usize])
+    {
+        moves.push((pos.x, y_2 as u8));
+    }
+
+    if pos.y == enp_rank {
+        for x in [pos.x as i8 - 1, pos.x as i8 + 1].iter().filter(|x| **x >= 0 && **x < state.width as i8) {
+            if enp & (1 << x) > 0 {
+                moves.push((*x as u8, y_1 as u8));
+            }
+        }
+    }
+
+    moves
+}
+
+fn mv_king(pos: Point<u8>, state: &BoardState) -> Vec<(u8, u8)> {
+    let mut moves = mv_betza(pieces::steps('K').unwrap(), pos.clone(), state);
+
+    // An Atomic king would blow itself up capturing
+    if state.variant.kind == Kind::Atomic {
+        moves.retain(|(x, y)| !is_piece(state.board[*y as usize][*x as usize]));
+    }
+
+    let ch = state.board[pos.y as usize][pos.x as usize];
+
+    let (checked, white, back) = match ch {
+        'k' => (state.b_check, false, 0),
+        'K' => (state.w_check, true, state.height - 1),
+        _ => return moves,
+    };
+
+    if checked || pos.y != back {
+        return moves;
+    }
+
+    let row = &state.board[back as usize];
+
+    for king_side in [true, false].iter() {
+        let rook_x = match state.castling[castling_index(white, *king_side)] {
+            Some(file) if castles_with(row[file as usize], white, state.variant) => file,
+            _ => continue,
+        };
+
+        let (king_to, rook_to) = castled_files(*king_side, state);
+
+        // Everything the king and the rook cross has to be empty, apart
+        // from the two of them
+        let lo = pos.x.min(king_to).min(rook_x).min(rook_to);
+        let hi = pos.x.max(king_to).max(rook_x).max(rook_to);
+        let blocked = (lo..=hi).any(|x| x != pos.x && x != rook_x && is_piece(row[x as usize]));
+
+        if blocked {
+            continue;
+        }
+
+        // The king may not pass through an attacked square
+        let path = if king_to > pos.x { pos.x + 1..=king_to } else { king_to..=pos.x - 1 };
+        let attacked = path.into_iter().any(|x| {
+            let mut state_ = state.clone();
+            state_.board[back as usize][pos.x as usize] = '-';
+            state_.board[back as usize][x as usize] = ch;
+            match white {
+                true => state_.wk_pos = (x, back),
+                false => state_.bk_pos = (x, back),
+            }
+            check_for_checks(if white { Player::White } else { Player::Black }, &mut state_)
+        });
+
+        if !attacked {
+            moves.push((rook_x, back));
+        }
+    }
+
+    moves
+}
+
+pub fn get_piece_moves(ch: char, pos: Point<u8>, state: &BoardState) -> Vec<(u8, u8)> {
+    match ch.to_ascii_uppercase() {
+        'P' => mv_pawn(pos, state),
+        'K' => mv_king(pos, state),
+        _ => match piece_steps(ch, state) {
+            Some(steps) => mv_betza(steps, pos, state),
+            None => Vec::<(u8, u8)>::new(),
+        },
+    }
+}
+
+// How a piece moves, a custom variant's own pieces come before the built-in ones
+pub fn piece_steps(ch: char, state: &BoardState) -> Option<&'static [Step]> {
+    match state.variant.custom().and_then(|custom| custom.piece(ch)) {
+        Some(steps) => Some(steps),
+        None => pieces::steps(ch),
+    }
+}
+
+// Squares the piece `ch` would attack from `from`, as `square_bit`s and
+// whatever stands on them. Lines stop at the first piece apart from
+// `ignore`, the square the piece comes from when it is only thinking of
+// moving to `from`
+pub fn attack_map(ch: char, from: (u8, u8), ignore: Option<(u8, u8)>, state: &BoardState) -> u128 {
+    let steps = match piece_steps(ch, state) {
+        Some(steps) => steps,
+        None => return 0,
+    };
+    let flip = if is_black_piece(ch) { -1 } else { 1 };
+    let mut map = 0;
+
+    for step in steps.iter().filter(|step| step.mode != Mode::Move) {
+        let (mut x, mut y) = (from.0 as i8, from.1 as i8);
+        let mut n = 0;
+
+        loop {
+            x += step.dx;
+            y += step.dy * flip;
+            n += 1;
+
+            if x < 0 || x >= state.width as i8 || y < 0 || y >= state.height as i8 {
+                break;
+            }
+
+            let sq = (x as u8, y as u8);
+            map |= square_bit(sq);
+
+            let blocked = is_piece(state.board[sq.1 as usize][sq.0 as usize]) && Some(sq) != ignore;
+            if blocked || (step.range != 0 && n >= step.range) {
+                break;
+            }
+        }
+    }
+
+    map
+}
+
+// Every square a piece of `plyr` attacks
+pub fn attacked_squares(plyr: Player, state: &BoardState) -> u128 {
+    squares(state)
+        .filter(|(x, y)| {
+            let ch = state.board[*y as usize][*x as usize];
+            is_piece(ch) && ch != DUCK && !is_opponent(plyr, ch)
+        })
+        .fold(0, |map, (x, y)| map | attack_map(state.board[y as usize][x as usize], (x, y), None, state))
+}
+
+// Pieces described by their steps, black's are mirrored so forward points
+// down the board
+fn mv_betza(steps: &[Step], pos: Point<u8>, state: &BoardState) -> Vec<(u8, u8)> {
+    let mut moves = Vec::<(u8, u8)>::new();
+    let ch = state.board[pos.y as usize][pos.x as usize];
+    let flip = if is_black_piece(ch) { -1 } else { 1 };
+
+    for step in steps.iter() {
+        let (mut x, mut y) = (pos.x as i8, pos.y as i8);
+        let mut n = 0;
+
+        loop {
+            x += step.dx;
+            y += step.dy * flip;
+            n += 1;
+
+            if x < 0 || x >= state.width as i8 || y < 0 || y >= state.height as i8 {
+                break;
+            }
+
+            let target = state.board[y as usize][x as usize];
+            let lands = match step.mode {
+                Mode::Any => !is_piece(target) || is_opponent(state.player, target),
+                Mode::Move => !is_piece(target),
+                Mode::Capture => is_opponent(state.player, target),
+            };
+            if lands && !moves.contains(&(x as u8, y as u8)) {
+                moves.push((x as u8, y as u8));
+            }
+
+            if is_piece(target) || (step.range != 0 && n >= step.range) {
+                break;
+            }
+        }
+    }
+
+    moves
+}
+
+pub fn check_for_checks(plyr: Player, state: &mut BoardState) -> bool {
+    if state.variant.kind == Kind::Antichess || state.variant.kind == Kind::Duck {
+        return false;
+    }
+
+    let k_pos = match state.king(plyr) {
+        Some(pos) => pos,
+        None => return false,
+    };
+
+    let atomic = state.variant.kind == Kind::Atomic;
+
+    // Capturing a king next to its own king would blow up both
+    if let Some(other) = state.king(plyr.switch()) {
+        if atomic && (other.0 as i8 - k_pos.0 as i8).abs() <= 1 && (other.1 as i8 - k_pos.1 as i8).abs() <= 1 {
+            return false;
+        }
+    }
+
+    let orig = state.player;
+    state.player = plyr.switch();
+
+    for (x, y) in squares(state) {
+        let ch = state.board[y as usize][x as usize];
+
+        let is_valid_piece = match plyr {
+            Player::White => is_black_piece(ch),
+            Player::Black => is_white_piece(ch),
+        };
+
+        if !is_valid_piece {
+            continue;
+        }
+
+        // Kings only attack their neighbours, castling never captures
+        let attacks = if ch == 'k' || ch == 'K' {
+            !atomic && (x as i8 - k_pos.0 as i8).abs() <= 1 && (y as i8 - k_pos.1 as i8).abs() <= 1
+        } else {
+            get_piece_moves(ch, Point::new(x, y), state).contains(&k_pos)
+        };
+
+        if attacks {
+            state.player = orig;
+            return true;
+        }
+    }
+
+    state.player = orig;
+    false
+}
+
+// How the game ended once it is over, in Antichess the side left without
+// moves wins
+pub fn outcome(state: &BoardState) -> Option<Outcome> {
+    outcome_with_moves(state, &legal_moves(state))
+}
+
+// Same as `outcome` for a position whose legal moves are already known
+pub fn outcome_with_moves(state: &BoardState, moves: &[Move]) -> Option<Outcome> {
+    match state.variant.kind {
+        Kind::RacingKings => return racing_kings_outcome(state, moves),
+        Kind::Duck => return duck_outcome(state, moves),
+        _ => (),
+    }
+
+    let goal = variant_goal_reached(state.player, state);
+    if !moves.is_empty() && !goal {
+        return None;
+    }
+
+    let checked = match state.player {
+        Player::White => state.w_check,
+        Player::Black => state.b_check,
+    };
+    // A Horde side without moves may also have no pieces left
+    let wiped_out = !squares(state).any(|(x, y)| {
+        let ch = state.board[y as usize][x as usize];
+        is_piece(ch) && ch != DUCK && !is_opponent(state.player, ch)
+    });
+
+    // Being left without moves wins in Antichess, otherwise it is mate, or
+    // stalemate and a draw
+    match state.variant.kind {
+        Kind::Antichess => Some(Outcome::Win(state.player)),
+        _ if goal || checked || wiped_out => Some(Outcome::Win(state.player.switch())),
+        _ => Some(Outcome::Draw),
+    }
+}
+
+// Duck chess is won by taking the king, a player left without moves wins too
+fn duck_outcome(state: &BoardState, moves: &[Move]) -> Option<Outcome> {
+    for plyr in [Player::White, Player::Black].iter() {
+        if state.king(*plyr).is_none() {
+            return Some(Outcome::Win(plyr.switch()));
+        }
+    }
+
+    if !state.duck_to_move && moves.is_empty() {
+        return Some(Outcome::Win(state.player));
+    }
+
+    None
+}
+
+// First king on the 8th rank wins, but when white gets there black has one
+// more move to draw by doing the same. Stalemate is a draw
+fn racing_kings_outcome(state: &BoardState, moves: &[Move]) -> Option<Outcome> {
+    let arrived = |plyr| state.king(plyr).map_or(false, |pos| pos.1 == 0);
+
+    match (arrived(Player::White), arrived(Player::Black)) {
+        (true, true) => return Some(Outcome::Draw),
+        (false, true) => return Some(Outcome::Win(Player::Black)),
+        (true, false) => {
+            let bk = state.bk_pos;
+            let can_follow = state.player == Player::Black
+                && legal_targets(bk, state).iter().any(|to| to.1 == 0);
+
+            if !can_follow {
+                return Some(Outcome::Win(Player::White));
+            }
+        }
+        (false, false) => (),
+    }
+
+    if moves.is_empty() {
+        return Some(Outcome::Draw);
+    }
+
+    None
+}
+
+// Hill reached, enough checks given or, in Atomic, the king blown up
+fn variant_goal_reached(plyr: Player, state: &BoardState) -> bool {
+    let on_goal = goal_squares(state).iter().any(|(x, y)| {
+        let ch = state.board[*y as usize][*x as usize];
+        ch == 'k' || ch == 'K'
+    });
+    let checked_out = checks_to_win(state.variant).map_or(false, |n| state.w_checks >= n || state.b_checks >= n);
+
+    if on_goal || checked_out {
+        return true;
+    }
+
+    // Atomic kings can be blown up
+    state.variant.kind == Kind::Atomic && state.king(plyr).is_none()
+}
@@ -0,0 +1,72 @@
+use std::fs;
+use std::path::{Path, PathBuf};
+
+use serde::{Deserialize, Serialize};
+
+use crate::rules::{self, BoardState, Move};
+use crate::settings::{self, TimeControl};
+use crate::variant::Variant;
+
+// Everything needed to pick an unfinished game back up
+#[derive(Serialize, Deserialize)]
+pub struct SavedGame {
+    pub start_fen: String,
+    // Moves in coordinate notation, e.g. "e2e4"
+    pub moves: Vec<String>,
+    pub white_checks: u8,
+    pub black_checks: u8,
+    pub white_ms: u64,
+    pub black_ms: u64,
+    pub clock_running: bool,
+    pub variant: Variant,
+    pub clock: Option<TimeControl>,
+}
+
+impl SavedGame {
+    // Written after every move, offered on the next start
+    pub fn autosave_path() -> Option<PathBuf> {
+        settings::project_dirs().map(|dirs| dirs.data_dir().join("autosave.toml"))
+    }
+
+    // Where the save/load keys put the game
+    pub fn quicksave_path() -> Option<PathBuf> {
+        settings::project_dirs().map(|dirs| dirs.data_dir().join("saved-game.toml"))
+    }
+
+    pub fn load(path: &Path) -> Result<Self, String> {
+        let text = fs::read_to_string(path).map_err(|e| format!("{}: {}", path.display(), e))?;
+        toml::from_str(&text).map_err(|e| format!("{}: {}", path.display(), e))
+    }
+
+    pub fn write(&self, path: &Path) -> Result<(), String> {
+        let text = toml::to_string_pretty(self).map_err(|e| e.to_string())?;
+
+        if let Some(dir) = path.parent() {
+            fs::create_dir_all(dir).map_err(|e| format!("{}: {}", dir.display(), e))?;
+        }
+
+        fs::write(path, text).map_err(|e| format!("{}: {}", path.display(), e))
+    }
+
+    // Play the moves from the starting position, rejecting anything illegal
+    pub fn replay(&self) -> Result<(BoardState, Vec<Move>), String> {
+        let mut state = BoardState::from_fen(&self.start_fen, self.variant)?;
+        let mut history = Vec::with_capacity(self.moves.len());
+
+        for text in self.moves.iter() {
+            let mv = Move::parse(text, state.height).ok_or(format!("bad move \"{}\"", text))?;
+
+            // Older saves wrote castling as the king's destination (e1g1)
+            let mv = rules::resolve_move(mv, &state)
+                .ok_or(format!("illegal move \"{}\" in {}", text, state.to_fen()))?;
+
+            state.make_move(mv);
+            history.push(mv);
+        }
+
+        state.w_checks = self.white_checks;
+        state.b_checks = self.black_checks;
+
+        Ok((state, history))
+    }
+}
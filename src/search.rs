@@ -0,0 +1,656 @@
+use std::sync::atomic::{AtomicBool, AtomicU64, Ordering};
+use std::sync::Arc;
+use std::thread;
+use std::time::{Duration, Instant};
+
+use rand::Rng;
+
+use crate::eval;
+use crate::rules::{self, BoardState, Move, Outcome};
+use crate::skill::Level;
+use crate::tablebase::{self, Wdl};
+use crate::tt::{self, Bound, Entry, Table};
+use crate::variant::Kind;
+
+// Score of delivering mate right now, mates further away score a bit less
+pub const MATE: i32 = 30_000;
+// Scores past this are mates, with the distance in plies taken off
+pub const MATE_BOUND: i32 = MATE - 1_000;
+// Tablebase wins score below every mate, nearer ones higher
+pub const TB_WIN: i32 = MATE_BOUND - 1_000;
+const INFINITY: i32 = MATE + 1;
+pub const MAX_DEPTH: u8 = 64;
+// Half width of the first aspiration window around the last score
+const ASPIRATION: i32 = 40;
+// Kept back from the clock for talking to the GUI
+const OVERHEAD: Duration = Duration::from_millis(30);
+// Moves assumed to be left in the game when the clock doesn't say
+const MOVES_TO_GO: u32 = 30;
+pub const MAX_THREADS: usize = 256;
+
+// Time left on the engine's clock
+#[derive(Clone, Copy, Debug)]
+pub struct Clock {
+    pub remaining: Duration,
+    pub increment: Duration,
+    // Moves until the next time control, sudden death if None
+    pub moves_to_go: Option<u32>,
+}
+
+// When to stop thinking, a search without any limit runs until stopped
+#[derive(Clone, Copy, Default, Debug)]
+pub struct Limits {
+    pub depth: Option<u8>,
+    pub nodes: Option<u64>,
+    pub movetime: Option<Duration>,
+    pub clock: Option<Clock>,
+    // Play below full strength
+    pub level: Option<Level>,
+}
+
+// Time budget of one move. The soft limit decides whether another
+// iteration is started, the hard one aborts the search under way
+pub struct TimeManager {
+    start: Instant,
+    soft: Option<Duration>,
+    hard: Option<Duration>,
+}
+
+impl TimeManager {
+    pub fn new(limits: &Limits) -> Self {
+        let (soft, hard) = match (limits.movetime, limits.clock) {
+            (Some(time), _) => (Some(time), Some(time)),
+            (None, Some(clock)) => {
+                let usable = clock.remaining.saturating_sub(OVERHEAD);
+                let moves_left = clock.moves_to_go.unwrap_or(MOVES_TO_GO).clamp(1, MOVES_TO_GO);
+                // Never bet more than half the clock unless this is the
+                // last move before the time control
+                let cap = match moves_left {
+                    1 => usable,
+                    _ => usable / 2,
+                };
+
+                let soft = (usable / moves_left + clock.increment * 3 / 4).min(cap);
+                (Some(soft), Some((soft * 4).min(cap)))
+            }
+            (None, None) => (None, None),
+        };
+
+        Self { start: Instant::now(), soft, hard }
+    }
+
+    pub fn elapsed(&self) -> Duration {
+        self.start.elapsed()
+    }
+
+    // A new iteration takes longer than all before it together, so don't
+    // start one past half the budget
+    fn next_iteration(&self) -> bool {
+        self.soft.map_or(true, |soft| self.elapsed() < soft / 2)
+    }
+
+    fn out_of_time(&self) -> bool {
+        self.hard.map_or(false, |hard| self.elapsed() >= hard)
+    }
+}
+
+// Progress report after each finished iteration
+pub struct Info {
+    pub depth: u8,
+    // Centipawns for the side to move, or MATE minus the plies to mate
+    pub score: i32,
+    pub nodes: u64,
+    pub elapsed: Duration,
+    pub pv: Vec<Move>,
+    // DTZ of the position when the move came from the tablebases
+    pub tablebase: Option<i32>,
+}
+
+// Iterative deepening alpha-beta search with a transposition table. With
+// more than one thread, helpers search the same position at the same time
+// and share what they find through the table (Lazy SMP)
+pub struct Search {
+    tt: Arc<Table>,
+    stop: Arc<AtomicBool>,
+    threads: usize,
+    // Set when the main thread is done, to stop the helpers
+    done: Arc<AtomicBool>,
+    // Nodes searched by the helpers so far
+    helper_nodes: Arc<AtomicU64>,
+    is_helper: bool,
+    timer: TimeManager,
+    node_limit: Option<u64>,
+    nodes: u64,
+    aborted: bool,
+    root_best: Option<Move>,
+    // Score every root move exactly so a weaker level can pick among them
+    spread: bool,
+    root_scores: Vec<(Move, i32)>,
+    // Keys of the game's positions before the root, for the next `go` only
+    history: Vec<u64>,
+    // The same plus the positions on the line being searched
+    path: Vec<u64>,
+}
+
+impl Search {
+    pub fn new(hash_mb: usize) -> Self {
+        Self {
+            tt: Arc::new(Table::new(hash_mb)),
+            stop: Arc::new(AtomicBool::new(false)),
+            threads: 1,
+            done: Arc::new(AtomicBool::new(false)),
+            helper_nodes: Arc::new(AtomicU64::new(0)),
+            is_helper: false,
+            timer: TimeManager::new(&Limits::default()),
+            node_limit: None,
+            nodes: 0,
+            aborted: false,
+            root_best: None,
+            spread: false,
+            root_scores: Vec::new(),
+            history: Vec::new(),
+            path: Vec::new(),
+        }
+    }
+
+    pub fn resize_tt(&mut self, hash_mb: usize) {
+        self.tt = Arc::new(Table::new(hash_mb));
+    }
+
+    pub fn set_threads(&mut self, threads: usize) {
+        self.threads = threads.clamp(1, MAX_THREADS);
+    }
+
+    // Nodes searched by all threads in the last `go`
+    pub fn nodes(&self) -> u64 {
+        self.nodes + self.helper_nodes.load(Ordering::Relaxed)
+    }
+
+    // Searcher for a helper thread, sharing the table and the stop flags
+    fn helper(&self) -> Self {
+        Self {
+            tt: Arc::clone(&self.tt),
+            stop: Arc::clone(&self.stop),
+            threads: 1,
+            done: Arc::clone(&self.done),
+            helper_nodes: Arc::clone(&self.helper_nodes),
+            is_helper: true,
+            timer: TimeManager::new(&Limits::default()),
+            node_limit: None,
+            nodes: 0,
+            aborted: false,
+            root_best: None,
+            spread: false,
+            root_scores: Vec::new(),
+            history: Vec::new(),
+            path: self.path.clone(),
+        }
+    }
+
+    // Positions the game went through before the one searched next, so
+    // the search knows which moves repeat one of them
+    pub fn set_history(&mut self, keys: Vec<u64>) {
+        self.history = keys;
+    }
+
+    // Forget everything learned, for a new game
+    pub fn clear(&self) {
+        self.tt.clear();
+    }
+
+    // Setting this flag from another thread ends the search under way, the
+    // best move found so far is still returned. It is not cleared by `go`
+    pub fn stop_flag(&self) -> Arc<AtomicBool> {
+        Arc::clone(&self.stop)
+    }
+
+    // Best move for the side to move, None when the game is over
+    pub fn go(&mut self, state: &BoardState, limits: &Limits, mut report: impl FnMut(&Info)) -> Option<Move> {
+        let level = limits.level.filter(|level| level.noise > 0 || level.blunder_margin > 0);
+        self.timer = TimeManager::new(limits);
+        self.node_limit = tighter(limits.nodes, limits.level.and_then(|level| level.nodes));
+        self.nodes = 0;
+        self.helper_nodes.store(0, Ordering::Relaxed);
+        self.aborted = false;
+        self.root_best = None;
+        self.spread = level.is_some();
+        self.path = std::mem::take(&mut self.history);
+
+        let moves = rules::legal_moves(state);
+        if rules::outcome_with_moves(state, &moves).is_some() {
+            return None;
+        }
+
+        // Endgames in the tablebases are played straight from them, weaker
+        // levels have to find their own way
+        if level.is_none() {
+            if let Some((mv, dtz)) = tablebase::root_move(state) {
+                let score = match dtz {
+                    d if d > 0 => TB_WIN - d,
+                    d if d < 0 => -TB_WIN - d,
+                    _ => 0,
+                };
+                report(&Info { depth: 1, score, nodes: 0, elapsed: self.timer.elapsed(), pv: vec![mv], tablebase: Some(dtz) });
+                return Some(mv);
+            }
+        }
+
+        // Nothing to think about with a single move on the clock, one ply
+        // still gives a score to report
+        let forced = moves.len() == 1 && limits.depth.is_none() && (limits.clock.is_some() || limits.movetime.is_some());
+        let limits = match forced {
+            true => Limits { depth: Some(1), ..*limits },
+            false => *limits,
+        };
+
+        // Weaker levels pick among exact root scores of their own
+        let helpers = match (level, forced) {
+            (Some(_), _) | (_, true) => 0,
+            (None, false) => self.threads - 1,
+        };
+        self.done.store(false, Ordering::Relaxed);
+
+        thread::scope(|scope| {
+            for id in 0..helpers {
+                let mut helper = self.helper();
+                let state = state.clone();
+                scope.spawn(move || helper.help(&state, id));
+            }
+
+            let best = self.iterate(state, &limits, level, &moves, report);
+            self.done.store(true, Ordering::Relaxed);
+            best
+        })
+    }
+
+    // Deepen one ply at a time until a limit is reached, reporting each
+    // finished iteration
+    fn iterate(
+        &mut self,
+        state: &BoardState,
+        limits: &Limits,
+        level: Option<Level>,
+        moves: &[Move],
+        mut report: impl FnMut(&Info),
+    ) -> Option<Move> {
+        let max_depth = tighter(limits.depth, limits.level.and_then(|level| level.depth));
+        let max_depth = max_depth.unwrap_or(MAX_DEPTH).clamp(1, MAX_DEPTH);
+        let mut best = moves[0];
+        let mut score = 0;
+        let mut root_scores = Vec::new();
+
+        for depth in 1..=max_depth {
+            self.root_scores.clear();
+            let result = self.aspiration(state, depth, score);
+
+            // An unfinished iteration may not have looked at the best move
+            // yet, only its first move is safe to take over
+            if self.aborted {
+                if depth == 1 {
+                    best = self.root_best.unwrap_or(best);
+                }
+                break;
+            }
+
+            best = self.root_best.unwrap_or(best);
+            root_scores = std::mem::take(&mut self.root_scores);
+            score = result;
+
+            report(&Info {
+                depth,
+                score,
+                nodes: self.nodes(),
+                elapsed: self.timer.elapsed(),
+                pv: self.pv(state, best, depth),
+                tablebase: None,
+            });
+
+            let mate_found = score.abs() >= MATE_BOUND && MATE - score.abs() <= depth as i32;
+            if mate_found || !self.timer.next_iteration() {
+                break;
+            }
+        }
+
+        match level {
+            Some(level) => Some(pick_weaker(&root_scores, level).unwrap_or(best)),
+            None => Some(best),
+        }
+    }
+
+    // A helper deepens until the main thread is done, every other one a
+    // ply ahead so they don't all search the same depth
+    fn help(&mut self, state: &BoardState, id: usize) {
+        let mut score = 0;
+
+        for depth in (1 + (id % 2) as u8)..=MAX_DEPTH {
+            score = self.aspiration(state, depth, score);
+            if self.aborted {
+                break;
+            }
+        }
+
+        self.helper_nodes.fetch_add(self.nodes % 256, Ordering::Relaxed);
+    }
+
+    // Score of the root searched `depth` plies deep, in a window around the
+    // last score that is widened until the score lands inside it
+    fn aspiration(&mut self, state: &BoardState, depth: u8, score: i32) -> i32 {
+        let mut delta = ASPIRATION;
+        let (mut alpha, mut beta) = match (depth, self.spread) {
+            (1..=3, _) | (_, true) => (-INFINITY, INFINITY),
+            _ => (score - delta, score + delta),
+        };
+
+        loop {
+            let result = self.negamax(state, depth, 0, alpha, beta);
+            if self.aborted {
+                return score;
+            }
+
+            if result <= alpha {
+                alpha = (alpha - delta).max(-INFINITY);
+            } else if result >= beta {
+                beta = (beta + delta).min(INFINITY);
+            } else {
+                return result;
+            }
+
+            delta *= 2;
+        }
+    }
+
+    fn should_stop(&self) -> bool {
+        self.stop.load(Ordering::Relaxed)
+            || self.done.load(Ordering::Relaxed)
+            || self.timer.out_of_time()
+            || self.node_limit.map_or(false, |limit| self.nodes >= limit)
+    }
+
+    // Every 256 nodes: see whether to stop, and let the main thread know
+    // how far a helper got
+    fn check_in(&mut self) {
+        if self.nodes % 256 != 0 {
+            return;
+        }
+        if self.is_helper && self.nodes > 0 {
+            self.helper_nodes.fetch_add(256, Ordering::Relaxed);
+        }
+        if self.should_stop() {
+            self.aborted = true;
+        }
+    }
+
+    // Score of the position for the side to move, searched `depth` plies deep
+    fn negamax(&mut self, state: &BoardState, depth: u8, ply: u8, mut alpha: i32, beta: i32) -> i32 {
+        self.check_in();
+        if self.aborted {
+            return 0;
+        }
+        self.nodes += 1;
+
+        let moves = rules::legal_moves(state);
+        if let Some(outcome) = rules::outcome_with_moves(state, &moves) {
+            return terminal_score(outcome, state, ply);
+        }
+
+        // A capture or pawn move into the tablebases ends the line
+        if ply > 0 && state.halfmove == 0 && tablebase::usable(state) {
+            match tablebase::probe_wdl(state) {
+                Some(wdl) if wdl.is_win() => return TB_WIN - ply as i32,
+                Some(wdl) if wdl.is_loss() => return -(TB_WIN - ply as i32),
+                Some(Wdl::Draw) => return 0,
+                _ => (),
+            }
+        }
+
+        // Back in a position seen before, the side to move can keep
+        // repeating it, so it is a draw
+        let key = tt::hash(state);
+        if ply > 0 && self.repeated(key, state) {
+            return 0;
+        }
+
+        if depth == 0 || ply >= MAX_DEPTH {
+            return self.quiesce(state, &moves, ply, alpha, beta);
+        }
+
+        let mut tt_move = None;
+
+        if let Some(entry) = self.tt.probe(key) {
+            tt_move = entry.mv;
+            let score = score_from_tt(entry.score, ply);
+
+            if ply > 0 && entry.depth >= depth {
+                match entry.bound {
+                    Bound::Exact => return score,
+                    Bound::Lower if score >= beta => return score,
+                    Bound::Upper if score <= alpha => return score,
+                    _ => (),
+                }
+            }
+        }
+
+        let moves = order_moves(moves, tt_move, state);
+        let orig_alpha = alpha;
+        let mut best = -INFINITY;
+        let mut best_move = None;
+        self.path.push(key);
+
+        for mv in moves {
+            let mut child = state.clone();
+            child.make_move(mv);
+
+            // A weaker level wants the exact score of every root move
+            let (lo, hi) = match ply == 0 && self.spread {
+                true => (-INFINITY, INFINITY),
+                false => (alpha, beta),
+            };
+
+            // The duck is moved by the same player, no change of sides
+            let score = match child.player == state.player {
+                true => self.negamax(&child, depth - 1, ply + 1, lo, hi),
+                false => -self.negamax(&child, depth - 1, ply + 1, -hi, -lo),
+            };
+
+            if self.aborted {
+                self.path.pop();
+                return 0;
+            }
+
+            if ply == 0 {
+                self.root_scores.push((mv, score));
+            }
+
+            if score > best {
+                best = score;
+                best_move = Some(mv);
+                if ply == 0 {
+                    self.root_best = Some(mv);
+                }
+            }
+
+            alpha = alpha.max(score);
+            if alpha >= beta {
+                break;
+            }
+        }
+        self.path.pop();
+
+        let bound = if best <= orig_alpha {
+            Bound::Upper
+        } else if best >= beta {
+            Bound::Lower
+        } else {
+            Bound::Exact
+        };
+
+        self.tt.store(Entry { key, mv: best_move, score: score_to_tt(best, ply), depth, bound });
+
+        best
+    }
+
+    // Whether the position came up before since the last capture or pawn
+    // move, before which it can't have
+    fn repeated(&self, key: u64, state: &BoardState) -> bool {
+        // Duck moves leave the move counter alone
+        let reversible = match state.variant.kind {
+            Kind::Duck => 2 * state.halfmove as usize,
+            _ => state.halfmove as usize,
+        };
+
+        self.path.iter().rev().take(reversible).any(|k| *k == key)
+    }
+
+    // Play out captures until the position is quiet, so the evaluation
+    // isn't taken in the middle of an exchange
+    fn quiesce(&mut self, state: &BoardState, moves: &[Move], ply: u8, mut alpha: i32, beta: i32) -> i32 {
+        let stand_pat = eval::evaluate(state);
+
+        // Duck moves don't change sides, leave those to the main search
+        if state.variant.kind == Kind::Duck || ply >= MAX_DEPTH {
+            return stand_pat;
+        }
+
+        if stand_pat >= beta {
+            return stand_pat;
+        }
+        alpha = alpha.max(stand_pat);
+
+        let captures: Vec<Move> = moves.iter().copied().filter(|mv| is_capture(*mv, state) || mv.promotion.is_some()).collect();
+
+        for mv in order_moves(captures, None, state) {
+            self.check_in();
+            if self.aborted {
+                return 0;
+            }
+            self.nodes += 1;
+
+            let mut child = state.clone();
+            child.make_move(mv);
+
+            let child_moves = rules::legal_moves(&child);
+            let score = match rules::outcome_with_moves(&child, &child_moves) {
+                Some(outcome) => -terminal_score(outcome, &child, ply + 1),
+                None => -self.quiesce(&child, &child_moves, ply + 1, -beta, -alpha),
+            };
+
+            if score >= beta {
+                return score;
+            }
+            alpha = alpha.max(score);
+        }
+
+        alpha
+    }
+
+    // Follow the stored best moves from the root, checking each is legal
+    // since another position may have taken its slot
+    fn pv(&self, state: &BoardState, best: Move, depth: u8) -> Vec<Move> {
+        let mut pv = vec![best];
+        let mut pos = state.clone();
+        pos.make_move(best);
+
+        while pv.len() < depth as usize {
+            let mv = match self.tt.probe(tt::hash(&pos)).and_then(|e| e.mv) {
+                Some(mv) if rules::legal_moves(&pos).contains(&mv) => mv,
+                _ => break,
+            };
+
+            pv.push(mv);
+            pos.make_move(mv);
+        }
+
+        pv
+    }
+}
+
+// Keys of the positions of a game before the one its moves lead to, for
+// `Search::set_history`
+pub fn game_keys(start: &BoardState, moves: &[Move]) -> Vec<u64> {
+    let mut state = start.clone();
+    let mut keys = Vec::with_capacity(moves.len());
+
+    for mv in moves {
+        keys.push(tt::hash(&state));
+        state.make_move(*mv);
+    }
+
+    keys
+}
+
+fn tighter<T: Ord>(a: Option<T>, b: Option<T>) -> Option<T> {
+    match (a, b) {
+        (Some(a), Some(b)) => Some(a.min(b)),
+        (a, b) => a.or(b),
+    }
+}
+
+// Any root move close enough to the best one, the best after adding some
+// random noise to each
+fn pick_weaker(scores: &[(Move, i32)], level: Level) -> Option<Move> {
+    let best = scores.iter().map(|(_, score)| *score).max()?;
+    let mut rng = rand::thread_rng();
+
+    scores
+        .iter()
+        .filter(|(_, score)| *score >= best - level.blunder_margin)
+        .map(|(mv, score)| (*mv, score + rng.gen_range(0..=level.noise)))
+        .max_by_key(|(_, score)| *score)
+        .map(|(mv, _)| mv)
+}
+
+fn terminal_score(outcome: Outcome, state: &BoardState, ply: u8) -> i32 {
+    match outcome {
+        Outcome::Win(plyr) if plyr == state.player => MATE - ply as i32,
+        Outcome::Win(_) => -(MATE - ply as i32),
+        Outcome::Draw => 0,
+    }
+}
+
+// Mate scores are stored as distance from the position, not from the root
+fn score_to_tt(score: i32, ply: u8) -> i32 {
+    match score {
+        s if s >= MATE_BOUND => s + ply as i32,
+        s if s <= -MATE_BOUND => s - ply as i32,
+        s => s,
+    }
+}
+
+fn score_from_tt(score: i32, ply: u8) -> i32 {
+    match score {
+        s if s >= MATE_BOUND => s - ply as i32,
+        s if s <= -MATE_BOUND => s + ply as i32,
+        s => s,
+    }
+}
+
+fn is_capture(mv: Move, state: &BoardState) -> bool {
+    mv.drop.is_none() && rules::is_capture(mv.from, mv.to, state)
+}
+
+// Stored best move first, then captures of the most valuable piece by the
+// cheapest one, then promotions
+fn order_moves(mut moves: Vec<Move>, tt_move: Option<Move>, state: &BoardState) -> Vec<Move> {
+    moves.sort_by_cached_key(|mv| {
+        if Some(*mv) == tt_move {
+            return i32::MIN;
+        }
+
+        let mut score = 0;
+        if is_capture(*mv, state) {
+            let victim = match state.board[mv.to.1 as usize][mv.to.0 as usize] {
+                '-' => 100,
+                ch => eval::piece_value(ch, state),
+            };
+            let attacker = eval::piece_value(state.board[mv.from.1 as usize][mv.from.0 as usize], state);
+            score += 10_000 + victim * 10 - attacker / 10;
+        }
+        if let Some(pc) = mv.promotion {
+            score += 5_000 + eval::piece_value(pc, state);
+        }
+
+        -score
+    });
+
+    moves
+}
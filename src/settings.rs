@@ -0,0 +1,138 @@
+use std::fs;
+use std::path::PathBuf;
+
+use directories::ProjectDirs;
+use serde::{Deserialize, Serialize};
+
+use crate::rules::Player;
+use crate::tt;
+use crate::variant::Variant;
+
+#[derive(Clone, Copy, PartialEq, Serialize, Deserialize)]
+pub struct TimeControl {
+    pub base_secs: u32,
+    pub increment_secs: u32,
+}
+
+impl TimeControl {
+    const PRESETS: [TimeControl; 5] = [
+        TimeControl { base_secs: 60, increment_secs: 0 },
+        TimeControl { base_secs: 180, increment_secs: 2 },
+        TimeControl { base_secs: 300, increment_secs: 0 },
+        TimeControl { base_secs: 600, increment_secs: 5 },
+        TimeControl { base_secs: 900, increment_secs: 10 },
+    ];
+
+    // Cycle no clock -> presets -> no clock
+    pub fn next(current: Option<TimeControl>) -> Option<TimeControl> {
+        match current.and_then(|tc| Self::PRESETS.iter().position(|p| *p == tc)) {
+            None => Some(Self::PRESETS[0]),
+            Some(idx) if idx + 1 < Self::PRESETS.len() => Some(Self::PRESETS[idx + 1]),
+            Some(_) => None,
+        }
+    }
+
+    pub fn label(&self) -> String {
+        format!("{}+{}", self.base_secs / 60, self.increment_secs)
+    }
+}
+
+// Everything the player picks that should survive a restart
+#[derive(Clone, Serialize, Deserialize)]
+#[serde(default)]
+pub struct Settings {
+    // Built-in theme name or path to a TOML theme
+    pub theme: String,
+    pub pieces: Option<PathBuf>,
+    pub flipped: bool,
+    // Computer skill level, 0 to `skill::MAX_LEVEL`
+    pub ai_strength: u8,
+    // Side the computer plays, None for two human players
+    pub computer: Option<Player>,
+    // Transposition table size of the computer player
+    pub hash_mb: usize,
+    // Search threads of the computer player and the analysis
+    pub threads: usize,
+    // Show the evaluation bar next to the board
+    pub eval_bar: bool,
+    // Directory of Syzygy tablebase files for standard chess endgames
+    pub syzygy_path: Option<PathBuf>,
+    pub window_width: f32,
+    pub window_height: f32,
+    pub variant: Variant,
+    pub clock: Option<TimeControl>,
+}
+
+impl Default for Settings {
+    fn default() -> Self {
+        Self {
+            theme: String::from("classic"),
+            pieces: None,
+            flipped: false,
+            ai_strength: 10,
+            computer: None,
+            hash_mb: tt::DEFAULT_HASH_MB,
+            threads: 1,
+            eval_bar: false,
+            syzygy_path: None,
+            window_width: 1000.,
+            window_height: 840.,
+            variant: Variant::default(),
+            clock: None,
+        }
+    }
+}
+
+// XDG config/data dirs (or the platform equivalent) for this game
+pub fn project_dirs() -> Option<ProjectDirs> {
+    ProjectDirs::from("", "rejnhed", "2-modded-chess")
+}
+
+impl Settings {
+    fn path() -> Option<PathBuf> {
+        project_dirs().map(|dirs| dirs.config_dir().join("settings.toml"))
+    }
+
+    pub fn load() -> Self {
+        let path = match Self::path() {
+            Some(path) => path,
+            None => return Self::default(),
+        };
+
+        let text = match fs::read_to_string(&path) {
+            Ok(text) => text,
+            Err(_) => return Self::default(),
+        };
+
+        match toml::from_str(&text) {
+            Ok(settings) => settings,
+            Err(e) => {
+                eprintln!("Ignoring invalid settings in {}: {}", path.display(), e);
+                Self::default()
+            }
+        }
+    }
+
+    pub fn save(&self) {
+        let path = match Self::path() {
+            Some(path) => path,
+            None => return,
+        };
+
+        let text = match toml::to_string_pretty(self) {
+            Ok(text) => text,
+            Err(e) => {
+                eprintln!("Could not serialize settings: {}", e);
+                return;
+            }
+        };
+
+        if let Some(dir) = path.parent() {
+            let _ = fs::create_dir_all(dir);
+        }
+
+        if let Err(e) = fs::write(&path, text) {
+            eprintln!("Could not write {}: {}", path.display(), e);
+        }
+    }
+}
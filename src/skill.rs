@@ -0,0 +1,48 @@
+// Playing strength of the computer, from 0 for a first game up to
+// `MAX_LEVEL` for everything the search can do
+pub const MAX_LEVEL: u8 = 20;
+
+// How a level holds the search back
+#[derive(Clone, Copy, Debug)]
+pub struct Level {
+    pub depth: Option<u8>,
+    pub nodes: Option<u64>,
+    // Random centipawns added to each root move's score before picking one
+    pub noise: i32,
+    // Root moves this much worse than the best can still be picked
+    pub blunder_margin: i32,
+}
+
+pub fn level(skill: u8) -> Level {
+    let skill = skill.min(MAX_LEVEL);
+    let weakness = (MAX_LEVEL - skill) as i32;
+
+    match skill {
+        MAX_LEVEL => Level { depth: None, nodes: None, noise: 0, blunder_margin: 0 },
+        _ => Level {
+            depth: Some(1 + skill / 3),
+            nodes: Some(200 << (skill / 2)),
+            noise: weakness * 12,
+            blunder_margin: weakness * weakness / 2,
+        },
+    }
+}
+
+// Rough Elo the level plays at, measured against nothing more precise than
+// our own games
+pub fn elo(skill: u8) -> u32 {
+    500 + skill.min(MAX_LEVEL) as u32 * 90
+}
+
+pub fn label(skill: u8) -> String {
+    let name = match skill.min(MAX_LEVEL) {
+        0..=4 => "Beginner",
+        5..=8 => "Casual",
+        9..=12 => "Club player",
+        13..=16 => "Expert",
+        17..=19 => "Master",
+        _ => "Full strength",
+    };
+
+    format!("{} {} (~{} Elo)", skill.min(MAX_LEVEL), name, elo(skill))
+}
@@ -0,0 +1,1468 @@
+use std::collections::{HashMap, HashSet};
+use std::convert::TryInto;
+use std::fs;
+use std::path::{Path, PathBuf};
+use std::sync::{Arc, Mutex, OnceLock, RwLock};
+
+use crate::rules::{self, BoardState, Move, Outcome, Player};
+use crate::variant::Kind;
+
+// First bytes of the Syzygy WDL and DTZ files
+const WDL_MAGIC: [u8; 4] = [0x71, 0xe8, 0x23, 0x5d];
+const DTZ_MAGIC: [u8; 4] = [0xd7, 0x66, 0x0c, 0xa5];
+const MAX_PIECES: usize = 7;
+
+// Flags of a compressed table
+const FLAG_STM: u8 = 1;
+const FLAG_MAPPED: u8 = 2;
+const FLAG_WIN_PLIES: u8 = 4;
+const FLAG_LOSS_PLIES: u8 = 8;
+const FLAG_WIDE: u8 = 16;
+const FLAG_SINGLE_VALUE: u8 = 128;
+
+// Piece codes inside the tables, black pieces have 8 added
+const PAWN: u8 = 1;
+const KING: u8 = 6;
+const BLACK: u8 = 8;
+
+// Outcome of a position with perfect play under the fifty move rule. A
+// cursed win is only a win without that rule, which this game doesn't have
+#[derive(Clone, Copy, PartialEq, Eq, PartialOrd, Ord, Debug)]
+pub enum Wdl {
+    Loss = -2,
+    BlessedLoss = -1,
+    Draw = 0,
+    CursedWin = 1,
+    Win = 2,
+}
+
+impl Wdl {
+    fn from_value(value: i32) -> Self {
+        match value {
+            v if v <= -2 => Wdl::Loss,
+            -1 => Wdl::BlessedLoss,
+            0 => Wdl::Draw,
+            1 => Wdl::CursedWin,
+            _ => Wdl::Win,
+        }
+    }
+
+    fn flip(self) -> Self {
+        Wdl::from_value(-(self as i32))
+    }
+
+    // With no fifty move rule the cursed results are the real ones
+    pub fn is_win(self) -> bool {
+        self >= Wdl::CursedWin
+    }
+
+    pub fn is_loss(self) -> bool {
+        self <= Wdl::BlessedLoss
+    }
+}
+
+#[derive(Clone, Copy, PartialEq, Eq, Hash, Debug)]
+enum Type {
+    Wdl,
+    Dtz,
+}
+
+// The tables found in the configured directory, each one read into memory
+// the first time a position needs it
+struct Tablebases {
+    dir: PathBuf,
+    wdl: HashSet<String>,
+    dtz: HashSet<String>,
+    // Most pieces in any table found
+    max_pieces: usize,
+    loaded: Mutex<HashMap<(String, Type), Option<Arc<Table>>>>,
+}
+
+static TABLEBASES: RwLock<Option<Arc<Tablebases>>> = RwLock::new(None);
+
+// Look for `.rtbw` and `.rtbz` files in `dir` and use them from now on, an
+// empty path turns probing off. Returns the number of tables found
+pub fn init(dir: &Path) -> Result<usize, String> {
+    if dir.as_os_str().is_empty() {
+        *TABLEBASES.write().unwrap() = None;
+        return Ok(0);
+    }
+
+    let entries = fs::read_dir(dir).map_err(|e| format!("{}: {}", dir.display(), e))?;
+    let mut tbs = Tablebases {
+        dir: dir.to_path_buf(),
+        wdl: HashSet::new(),
+        dtz: HashSet::new(),
+        max_pieces: 0,
+        loaded: Mutex::new(HashMap::new()),
+    };
+
+    for entry in entries.flatten() {
+        let path = entry.path();
+        let (name, ext) = match (path.file_stem().and_then(|s| s.to_str()), path.extension().and_then(|s| s.to_str())) {
+            (Some(name), Some(ext)) => (name.to_string(), ext),
+            _ => continue,
+        };
+        if !valid_name(&name) {
+            continue;
+        }
+
+        let pieces = name.len() - 1;
+        match ext {
+            "rtbw" => {
+                tbs.max_pieces = tbs.max_pieces.max(pieces);
+                tbs.wdl.insert(name);
+            }
+            "rtbz" => {
+                tbs.dtz.insert(name);
+            }
+            _ => (),
+        }
+    }
+
+    let found = tbs.wdl.len() + tbs.dtz.len();
+    *TABLEBASES.write().unwrap() = match tbs.wdl.is_empty() {
+        true => None,
+        false => Some(Arc::new(tbs)),
+    };
+
+    Ok(found)
+}
+
+// "KRPvKR" and the like, a king on each side and at most seven pieces
+fn valid_name(name: &str) -> bool {
+    match name.split_once('v') {
+        Some((white, black)) => {
+            white.starts_with('K')
+                && black.starts_with('K')
+                && name.len() - 1 <= MAX_PIECES
+                && name.chars().filter(|c| *c != 'v').all(|c| "KQRBNP".contains(c))
+        }
+        None => false,
+    }
+}
+
+fn tablebases() -> Option<Arc<Tablebases>> {
+    TABLEBASES.read().unwrap().clone()
+}
+
+// Tables are for standard chess on 8x8 only, and know nothing of castling
+pub fn usable(state: &BoardState) -> bool {
+    let variant = state.variant;
+    if variant.kind != Kind::Standard || variant.king_of_the_hill || variant.three_check {
+        return false;
+    }
+    if state.width != 8 || state.height != 8 || state.castling.iter().any(|c| c.is_some()) {
+        return false;
+    }
+
+    let max = match tablebases() {
+        Some(tbs) => tbs.max_pieces,
+        None => return false,
+    };
+
+    rules::squares(state).filter(|(x, y)| rules::is_piece(state.board[*y as usize][*x as usize])).count() <= max
+}
+
+// Win, draw or loss for the side to move
+pub fn probe_wdl(state: &BoardState) -> Option<Wdl> {
+    let tbs = tablebases()?;
+    search(&tbs, state, false).map(|(wdl, _)| wdl)
+}
+
+// Move that wins fastest, draws or loses slowest from a position that is
+// in the tables, with the DTZ of the position
+pub fn root_move(state: &BoardState) -> Option<(Move, i32)> {
+    if !usable(state) {
+        return None;
+    }
+
+    let tbs = tablebases()?;
+    let mut best: Option<(Move, i32, i32)> = None;
+
+    for mv in rules::legal_moves(state) {
+        let mut next = state.clone();
+        next.make_move(mv);
+
+        let moves = rules::legal_moves(&next);
+        let dtz = if rules::outcome_with_moves(&next, &moves) == Some(Outcome::Win(state.player)) {
+            // Mate
+            1
+        } else if next.halfmove == 0 {
+            before_zeroing(search(&tbs, &next, false)?.0.flip())
+        } else {
+            match -dtz(&tbs, &next)? {
+                d if d > 0 => d + 1,
+                d if d < 0 => d - 1,
+                _ => 0,
+            }
+        };
+
+        // Fastest win first, then draws, then the slowest loss
+        let rank = match dtz {
+            d if d > 0 => 1_000_000 - d,
+            d if d < 0 => -1_000_000 - d,
+            _ => 0,
+        };
+        if best.map_or(true, |(_, _, r)| rank > r) {
+            best = Some((mv, dtz, rank));
+        }
+    }
+
+    best.map(|(mv, dtz, _)| (mv, dtz))
+}
+
+// DTZ of a position whose best move is a capture or pawn move
+fn before_zeroing(wdl: Wdl) -> i32 {
+    match wdl {
+        Wdl::Win => 1,
+        Wdl::CursedWin => 101,
+        Wdl::BlessedLoss => -101,
+        Wdl::Loss => -1,
+        Wdl::Draw => 0,
+    }
+}
+
+fn is_pawn_move(mv: Move, state: &BoardState) -> bool {
+    state.board[mv.from.1 as usize][mv.from.0 as usize].to_ascii_uppercase() == 'P'
+}
+
+// The tables assume no captures are possible, so the captures (and pawn
+// moves when `zeroing`) are searched first. The flag tells whether the
+// result is reached by a capture or pawn move
+fn search(tbs: &Tablebases, state: &BoardState, zeroing: bool) -> Option<(Wdl, bool)> {
+    let moves = rules::legal_moves(state);
+    let mut best = Wdl::Loss;
+    let mut searched = 0;
+
+    for mv in moves.iter() {
+        let capture = rules::is_capture(mv.from, mv.to, state);
+        if !capture && (!zeroing || !is_pawn_move(*mv, state)) {
+            continue;
+        }
+        searched += 1;
+
+        let mut next = state.clone();
+        next.make_move(*mv);
+        let value = search(tbs, &next, false)?.0.flip();
+
+        if value > best {
+            best = value;
+            if value == Wdl::Win {
+                return Some((value, true));
+            }
+        }
+    }
+
+    // Every move was looked at, so the table isn't needed
+    let complete = searched > 0 && searched == moves.len();
+    let value = match complete {
+        true => best,
+        false => match probe_table(tbs, state, Type::Wdl, Wdl::Draw)? {
+            Stored::Value(value) => Wdl::from_value(value - 2),
+            Stored::OtherSide => return None,
+        },
+    };
+
+    match best >= value {
+        true => Some((best, best > Wdl::Draw || complete)),
+        false => Some((value, false)),
+    }
+}
+
+
+// Plies to the next capture or pawn move that keeps the result with best
+// play, positive when the side to move wins, 0 for a draw
+fn dtz(tbs: &Tablebases, state: &BoardState) -> Option<i32> {
+    let (wdl, zeroing) = search(tbs, state, true)?;
+
+    if wdl == Wdl::Draw {
+        return Some(0);
+    }
+    // The table may hold anything for these, e.g. a losing en passant
+    if zeroing {
+        return Some(before_zeroing(wdl));
+    }
+
+    let sign = (wdl as i32).signum();
+    if let Stored::Value(value) = probe_table(tbs, state, Type::Dtz, wdl)? {
+        let cursed = matches!(wdl, Wdl::CursedWin | Wdl::BlessedLoss);
+        return Some((value + 100 * cursed as i32) * sign);
+    }
+
+    // The table only holds the other side to move, so look one ply ahead
+    // for the move that keeps the result best
+    let mut best = i32::MAX;
+
+    for mv in rules::legal_moves(state) {
+        let zeroing = rules::is_capture(mv.from, mv.to, state) || is_pawn_move(mv, state);
+        let mut next = state.clone();
+        next.make_move(mv);
+
+        // A capture or pawn move counts from before it is made
+        let mut value = match zeroing {
+            true => -before_zeroing(search(tbs, &next, false)?.0),
+            false => -dtz(tbs, &next)?,
+        };
+
+        let checked = match next.player {
+            Player::White => next.w_check,
+            Player::Black => next.b_check,
+        };
+        if value == 1 && checked && rules::legal_moves(&next).is_empty() {
+            best = 1;
+        }
+        if !zeroing {
+            value += value.signum();
+        }
+        if value < best && value.signum() == sign {
+            best = value;
+        }
+    }
+
+    // No legal moves, mated
+    match best {
+        i32::MAX => Some(-1),
+        best => Some(best),
+    }
+}
+
+// What a table holds for a position
+enum Stored {
+    Value(i32),
+    // DTZ tables only store one side to move
+    OtherSide,
+}
+
+// The value stored for the position, only right when no capture is better.
+// `wdl` is the known result when reading a DTZ table
+fn probe_table(tbs: &Tablebases, state: &BoardState, kind: Type, wdl: Wdl) -> Option<Stored> {
+    let position = Position::new(state);
+
+    // Two bare kings
+    if position.count == 2 {
+        return Some(Stored::Value(match kind {
+            Type::Wdl => Wdl::Draw as i32 + 2,
+            Type::Dtz => 0,
+        }));
+    }
+
+    let (name, black_stronger) = tbs.find(&position, kind)?;
+    let table = tbs.table(&name, kind)?;
+
+    table.probe(&position, black_stronger, wdl)
+}
+
+// The board as the tables see it, squares from a1 = 0 to h8 = 63
+struct Position {
+    board: [u8; 64],
+    black_to_move: bool,
+    count: usize,
+}
+
+impl Position {
+    fn new(state: &BoardState) -> Self {
+        let mut board = [0; 64];
+        let mut count = 0;
+
+        for (x, y) in rules::squares(state) {
+            let ch = state.board[y as usize][x as usize];
+            let code = match "PNBRQK".find(ch.to_ascii_uppercase()) {
+                Some(idx) if rules::is_piece(ch) => idx as u8 + PAWN,
+                _ => continue,
+            };
+
+            board[(7 - y as usize) * 8 + x as usize] = match rules::is_white_piece(ch) {
+                true => code,
+                false => code + BLACK,
+            };
+            count += 1;
+        }
+
+        Self { board, black_to_move: state.player == Player::Black, count }
+    }
+
+    // Pieces of one side as in a table name, e.g. "KRP"
+    fn material(&self, color: u8) -> String {
+        "KQRBNP"
+            .chars()
+            .zip([KING, 5, 4, 3, 2, PAWN].iter())
+            .flat_map(|(ch, code)| {
+                let n = self.board.iter().filter(|pc| **pc == code + color).count();
+                std::iter::repeat(ch).take(n)
+            })
+            .collect()
+    }
+}
+
+impl Tablebases {
+    // Table holding the position, and whether it is stored with the colours
+    // swapped
+    fn find(&self, position: &Position, kind: Type) -> Option<(String, bool)> {
+        let names = match kind {
+            Type::Wdl => &self.wdl,
+            Type::Dtz => &self.dtz,
+        };
+        let (white, black) = (position.material(0), position.material(BLACK));
+
+        let name = format!("{}v{}", white, black);
+        if names.contains(&name) {
+            return Some((name, false));
+        }
+
+        let name = format!("{}v{}", black, white);
+        names.contains(&name).then(|| (name, true))
+    }
+
+    fn table(&self, name: &str, kind: Type) -> Option<Arc<Table>> {
+        let mut loaded = self.loaded.lock().unwrap();
+
+        loaded
+            .entry((name.to_string(), kind))
+            .or_insert_with(|| {
+                let ext = match kind {
+                    Type::Wdl => "rtbw",
+                    Type::Dtz => "rtbz",
+                };
+                let path = self.dir.join(format!("{}.{}", name, ext));
+
+                match Table::load(&path, name, kind) {
+                    Ok(table) => Some(Arc::new(table)),
+                    Err(e) => {
+                        eprintln!("Ignoring tablebase {}: {}", path.display(), e);
+                        None
+                    }
+                }
+            })
+            .clone()
+    }
+}
+
+// Index tables shared by every table file
+struct Maps {
+    // Ways to pick k of n squares
+    binomial: [[u64; 64]; MAX_PIECES],
+    map_b1h1h7: [u64; 64],
+    map_a1d1d4: [u64; 64],
+    map_kk: [[u64; 64]; 10],
+    map_pawns: [u64; 64],
+    lead_pawn_idx: [[u64; 64]; 6],
+    lead_pawns_size: [[u64; 4]; 6],
+}
+
+fn file_of(sq: usize) -> usize {
+    sq & 7
+}
+
+fn rank_of(sq: usize) -> usize {
+    sq >> 3
+}
+
+// Above the a1-h8 diagonal when positive
+fn off_diagonal(sq: usize) -> i32 {
+    rank_of(sq) as i32 - file_of(sq) as i32
+}
+
+fn flip_file(sq: usize) -> usize {
+    sq ^ 7
+}
+
+fn maps() -> &'static Maps {
+    static MAPS: OnceLock<Maps> = OnceLock::new();
+
+    MAPS.get_or_init(|| {
+        let mut m = Maps {
+            binomial: [[0; 64]; MAX_PIECES],
+            map_b1h1h7: [0; 64],
+            map_a1d1d4: [0; 64],
+            map_kk: [[0; 64]; 10],
+            map_pawns: [0; 64],
+            lead_pawn_idx: [[0; 64]; 6],
+            lead_pawns_size: [[0; 4]; 6],
+        };
+
+        // Squares below the a1-h8 diagonal to 0..27
+        let mut code = 0;
+        for sq in 0..64 {
+            if off_diagonal(sq) < 0 {
+                m.map_b1h1h7[sq] = code;
+                code += 1;
+            }
+        }
+
+        // The a1-d1-d4 triangle to 0..9, the diagonal last
+        let mut diagonal = Vec::new();
+        code = 0;
+        for sq in 0..=27 {
+            if off_diagonal(sq) < 0 && file_of(sq) <= 3 {
+                m.map_a1d1d4[sq] = code;
+                code += 1;
+            } else if off_diagonal(sq) == 0 && file_of(sq) <= 3 {
+                diagonal.push(sq);
+            }
+        }
+        for sq in diagonal {
+            m.map_a1d1d4[sq] = code;
+            code += 1;
+        }
+
+        // The 462 ways to place two kings with the first in the triangle,
+        // both on the diagonal last
+        let mut both_on_diagonal = Vec::new();
+        code = 0;
+        for idx in 0..10 {
+            for s1 in 0..=27 {
+                if m.map_a1d1d4[s1] != idx || (idx == 0 && s1 != 1) {
+                    continue;
+                }
+                for s2 in 0..64 {
+                    let near = (file_of(s1) as i32 - file_of(s2) as i32).abs() <= 1
+                        && (rank_of(s1) as i32 - rank_of(s2) as i32).abs() <= 1;
+                    if near || (off_diagonal(s1) == 0 && off_diagonal(s2) > 0) {
+                        continue;
+                    }
+                    if off_diagonal(s1) == 0 && off_diagonal(s2) == 0 {
+                        both_on_diagonal.push((idx as usize, s2));
+                    } else {
+                        m.map_kk[idx as usize][s2] = code;
+                        code += 1;
+                    }
+                }
+            }
+        }
+        for (idx, s2) in both_on_diagonal {
+            m.map_kk[idx][s2] = code;
+            code += 1;
+        }
+
+        m.binomial[0][0] = 1;
+        for n in 1..64 {
+            for k in 0..MAX_PIECES.min(n + 1) {
+                m.binomial[k][n] =
+                    if k > 0 { m.binomial[k - 1][n - 1] } else { 0 } + if k < n { m.binomial[k][n - 1] } else { 0 };
+            }
+        }
+
+        // Leading pawns, up to five of them, counted per file of the one
+        // nearest the edge and lowest
+        // Counted down before use, the last pair gets 1 and 0
+        let mut available = 49;
+        for lead in 1..=5 {
+            for file in 0..4 {
+                let mut idx = 0;
+                for rank in 1..7 {
+                    let sq = rank * 8 + file;
+                    if lead == 1 {
+                        available -= 2;
+                        m.map_pawns[sq] = available;
+                        m.map_pawns[flip_file(sq)] = available - 1;
+                    }
+                    m.lead_pawn_idx[lead][sq] = idx;
+                    idx += m.binomial[lead - 1][m.map_pawns[sq] as usize];
+                }
+                m.lead_pawns_size[lead][file] = idx;
+            }
+        }
+
+        m
+    })
+}
+
+fn byte(data: &[u8], pos: usize) -> Option<u8> {
+    data.get(pos).copied()
+}
+
+fn le16(data: &[u8], pos: usize) -> Option<u16> {
+    Some(u16::from_le_bytes(data.get(pos..pos + 2)?.try_into().ok()?))
+}
+
+fn le32(data: &[u8], pos: usize) -> Option<u32> {
+    Some(u32::from_le_bytes(data.get(pos..pos + 4)?.try_into().ok()?))
+}
+
+fn be32(data: &[u8], pos: usize) -> Option<u32> {
+    Some(u32::from_be_bytes(data.get(pos..pos + 4)?.try_into().ok()?))
+}
+
+fn be64(data: &[u8], pos: usize) -> Option<u64> {
+    Some(u64::from_be_bytes(data.get(pos..pos + 8)?.try_into().ok()?))
+}
+
+// How one side (and one leading pawn file) of a table is compressed: the
+// pieces are indexed in groups, and the values are Huffman coded symbols
+// standing for runs of values. Positions are offsets into the file
+#[derive(Clone, Default)]
+struct Pairs {
+    flags: u8,
+    pieces: [u8; MAX_PIECES],
+    group_len: [usize; MAX_PIECES + 1],
+    group_idx: [u64; MAX_PIECES + 1],
+    size_of_block: u64,
+    span: u64,
+    sparse_index_size: u64,
+    blocks: u64,
+    block_length_size: u64,
+    // Also the value of a table holding a single value
+    min_sym_len: u8,
+    lowest_sym: usize,
+    base64: Vec<u64>,
+    sym_len: Vec<u8>,
+    btree: usize,
+    sparse_index: usize,
+    block_length: usize,
+    data: usize,
+    map_idx: [usize; 4],
+}
+
+// One table file read into memory
+struct Table {
+    data: Vec<u8>,
+    kind: Type,
+    symmetric: bool,
+    has_pawns: bool,
+    // Some piece is alone of its kind, so three pieces lead instead of the
+    // two kings
+    unique: bool,
+    // Lead pawns' side first
+    pawn_count: [usize; 2],
+    // Value maps of a DTZ table
+    map: usize,
+    // By side to move, then by leading pawn file
+    pairs: Vec<Vec<Pairs>>,
+}
+
+impl Table {
+    fn load(path: &Path, name: &str, kind: Type) -> Result<Self, String> {
+        let data = fs::read(path).map_err(|e| e.to_string())?;
+        let magic = match kind {
+            Type::Wdl => WDL_MAGIC,
+            Type::Dtz => DTZ_MAGIC,
+        };
+        if data.len() < 5 || data[..4] != magic {
+            return Err(String::from("not a Syzygy table"));
+        }
+
+        Table::parse(data, name, kind).ok_or_else(|| String::from("truncated file"))
+    }
+
+    fn parse(data: Vec<u8>, name: &str, kind: Type) -> Option<Self> {
+        let (white, black) = name.split_once('v')?;
+        let count = |side: &str, pc: char| side.chars().filter(|c| *c == pc).count();
+        let pieces = name.len() - 1;
+        let unique = "PNBRQ".chars().any(|pc| count(white, pc) == 1 || count(black, pc) == 1);
+        let (wp, bp) = (count(white, 'P'), count(black, 'P'));
+        let lead_white = bp == 0 || (wp > 0 && bp >= wp);
+
+        let mut table = Table {
+            data,
+            kind,
+            symmetric: white == black,
+            unique,
+            has_pawns: wp + bp > 0,
+            pawn_count: match lead_white {
+                true => [wp, bp],
+                false => [bp, wp],
+            },
+            map: 0,
+            pairs: Vec::new(),
+        };
+
+        let sides = match kind == Type::Wdl && !table.symmetric {
+            true => 2,
+            false => 1,
+        };
+        let files = match table.has_pawns {
+            true => 4,
+            false => 1,
+        };
+        // Pawns on both sides
+        let both = table.has_pawns && table.pawn_count[1] > 0;
+        table.pairs = vec![vec![Pairs::default(); files]; sides];
+
+        let data = &table.data;
+        let mut pos = 5;
+
+        for file in 0..files {
+            let first = byte(data, pos)?;
+            let second = match both {
+                true => byte(data, pos + 1)?,
+                false => 0xff,
+            };
+            let order = [[first & 0xf, second & 0xf], [first >> 4, second >> 4]];
+            pos += 1 + both as usize;
+
+            for k in 0..pieces {
+                let b = byte(data, pos)?;
+                for side in 0..sides {
+                    table.pairs[side][file].pieces[k] = match side {
+                        0 => b & 0xf,
+                        _ => b >> 4,
+                    };
+                }
+                pos += 1;
+            }
+
+            for side in 0..sides {
+                let pairs = &mut table.pairs[side][file];
+                set_groups(pairs, pieces, table.has_pawns, unique, table.pawn_count, order[side], file);
+            }
+        }
+        pos += pos & 1;
+
+        for file in 0..files {
+            for side in 0..sides {
+                pos = set_sizes(&mut table.pairs[side][file], data, pos)?;
+            }
+        }
+
+        if kind == Type::Dtz {
+            table.map = pos;
+            for file in 0..files {
+                let pairs = &mut table.pairs[0][file];
+                if pairs.flags & FLAG_MAPPED == 0 {
+                    continue;
+                }
+
+                if pairs.flags & FLAG_WIDE != 0 {
+                    pos += pos & 1;
+                    for i in 0..4 {
+                        pairs.map_idx[i] = (pos - table.map) / 2 + 1;
+                        pos += 2 * le16(data, pos)? as usize + 2;
+                    }
+                } else {
+                    for i in 0..4 {
+                        pairs.map_idx[i] = pos - table.map + 1;
+                        pos += byte(data, pos)? as usize + 1;
+                    }
+                }
+            }
+            pos += pos & 1;
+        }
+
+        for file in 0..files {
+            for side in 0..sides {
+                let pairs = &mut table.pairs[side][file];
+                pairs.sparse_index = pos;
+                pos += pairs.sparse_index_size as usize * 6;
+            }
+        }
+        for file in 0..files {
+            for side in 0..sides {
+                let pairs = &mut table.pairs[side][file];
+                pairs.block_length = pos;
+                pos += pairs.block_length_size as usize * 2;
+            }
+        }
+        for file in 0..files {
+            for side in 0..sides {
+                let pairs = &mut table.pairs[side][file];
+                pos = (pos + 0x3f) & !0x3f;
+                pairs.data = pos;
+                pos += (pairs.blocks * pairs.size_of_block) as usize;
+            }
+        }
+
+        match pos <= data.len() {
+            true => Some(table),
+            false => None,
+        }
+    }
+
+    // Raw value of a WDL table, or the DTZ of a DTZ table in plies
+    fn probe(&self, position: &Position, black_stronger: bool, wdl: Wdl) -> Option<Stored> {
+        let m = maps();
+        let flip = black_stronger || (self.symmetric && position.black_to_move);
+        let (flip_color, flip_squares) = match flip {
+            true => (BLACK, 56),
+            false => (0, 0),
+        };
+        let stm = (flip != position.black_to_move) as usize;
+        let sides = self.pairs.len();
+
+        let mut squares = [0usize; MAX_PIECES];
+        let mut pieces = [0u8; MAX_PIECES];
+        let mut size = 0;
+        let mut lead = 0;
+        let mut file = 0;
+        let mut lead_pawn = 0;
+
+        // The leading pawns' colour is the table's reference, the one
+        // furthest toward the edge and lowest leads
+        if self.has_pawns {
+            lead_pawn = self.pairs[0][0].pieces[0] ^ flip_color;
+            for sq in 0..64 {
+                if position.board[sq] == lead_pawn {
+                    squares[size] = sq ^ flip_squares;
+                    size += 1;
+                }
+            }
+            lead = size;
+
+            let max = (0..lead).rev().max_by_key(|i| m.map_pawns[squares[*i]])?;
+            squares.swap(0, max);
+            file = file_of(squares[0]).min(7 - file_of(squares[0]));
+        }
+
+        let pairs = &self.pairs[stm % sides][file];
+
+        // DTZ tables keep one side to move
+        if self.kind == Type::Dtz {
+            let stored = (self.pairs[0][file].flags & FLAG_STM) as usize;
+            if stored != stm && !(self.symmetric && !self.has_pawns) {
+                return Some(Stored::OtherSide);
+            }
+        }
+
+        for sq in 0..64 {
+            let pc = position.board[sq];
+            if pc == 0 || (self.has_pawns && pc == lead_pawn) {
+                continue;
+            }
+            squares[size] = sq ^ flip_squares;
+            pieces[size] = pc ^ flip_color;
+            size += 1;
+        }
+
+        // Put the pieces in the order the table was encoded with
+        for i in lead..size.saturating_sub(1) {
+            for j in i + 1..size {
+                if pairs.pieces[i] == pieces[j] {
+                    pieces.swap(i, j);
+                    squares.swap(i, j);
+                    break;
+                }
+            }
+        }
+
+        // Mirror so the leading piece is on the a-d files
+        if file_of(squares[0]) > 3 {
+            for sq in squares[..size].iter_mut() {
+                *sq = flip_file(*sq);
+            }
+        }
+
+        let mut idx;
+        if self.has_pawns {
+            idx = m.lead_pawn_idx[lead][squares[0]];
+            squares[1..lead].sort_by_key(|sq| m.map_pawns[*sq]);
+            for i in 1..lead {
+                idx += m.binomial[i][m.map_pawns[squares[i]] as usize];
+            }
+        } else {
+            // Then below the 5th rank and below the a1-h8 diagonal
+            if rank_of(squares[0]) > 3 {
+                for sq in squares[..size].iter_mut() {
+                    *sq ^= 56;
+                }
+            }
+            for i in 0..pairs.group_len[0] {
+                match off_diagonal(squares[i]) {
+                    0 => continue,
+                    d if d > 0 => {
+                        for sq in squares[i..size].iter_mut() {
+                            *sq = ((*sq >> 3) | (*sq << 3)) & 63;
+                        }
+                    }
+                    _ => (),
+                }
+                break;
+            }
+
+            idx = match self.unique {
+                true => {
+                    let s = &squares;
+                    let adjust1 = (s[1] > s[0]) as u64;
+                    let adjust2 = (s[2] > s[0]) as u64 + (s[2] > s[1]) as u64;
+                    let (r0, r1, r2) = (rank_of(s[0]) as u64, rank_of(s[1]) as u64, rank_of(s[2]) as u64);
+
+                    if off_diagonal(s[0]) != 0 {
+                        (m.map_a1d1d4[s[0]] * 63 + (s[1] as u64 - adjust1)) * 62 + s[2] as u64 - adjust2
+                    } else if off_diagonal(s[1]) != 0 {
+                        (6 * 63 + r0 * 28 + m.map_b1h1h7[s[1]]) * 62 + s[2] as u64 - adjust2
+                    } else if off_diagonal(s[2]) != 0 {
+                        6 * 63 * 62 + 4 * 28 * 62 + r0 * 7 * 28 + (r1 - adjust1) * 28 + m.map_b1h1h7[s[2]]
+                    } else {
+                        6 * 63 * 62 + 4 * 28 * 62 + 4 * 7 * 28 + r0 * 7 * 6 + (r1 - adjust1) * 6 + (r2 - adjust2)
+                    }
+                }
+                false => m.map_kk[m.map_a1d1d4[squares[0]] as usize][squares[1]],
+            };
+        }
+
+        // The other groups, each one in ascending order, skipping the
+        // squares taken by earlier groups
+        idx *= pairs.group_idx[0];
+        let mut start = pairs.group_len[0];
+        let mut remaining_pawns = self.has_pawns && self.pawn_count[1] > 0;
+        let mut next = 1;
+
+        while pairs.group_len[next] != 0 {
+            let len = pairs.group_len[next];
+            squares[start..start + len].sort_unstable();
+
+            let mut n = 0;
+            for i in 0..len {
+                let sq = squares[start + i];
+                let adjust = squares[..start].iter().filter(|s| sq > **s).count();
+                n += m.binomial[i + 1][sq - adjust - 8 * remaining_pawns as usize];
+            }
+
+            remaining_pawns = false;
+            idx += n * pairs.group_idx[next];
+            start += len;
+            next += 1;
+        }
+
+        let value = self.decompress(pairs, idx)?;
+        match self.kind {
+            Type::Dtz => self.map_dtz(file, value, wdl).map(Stored::Value),
+            Type::Wdl => Some(Stored::Value(value)),
+        }
+    }
+
+    // DTZ in plies from the stored value, which may be in moves
+    fn map_dtz(&self, file: usize, value: i32, wdl: Wdl) -> Option<i32> {
+        let pairs = &self.pairs[0][file];
+        let slot = match wdl {
+            Wdl::Loss => 1,
+            Wdl::BlessedLoss => 3,
+            Wdl::Draw => 0,
+            Wdl::CursedWin => 2,
+            Wdl::Win => 0,
+        };
+        let mut value = value;
+
+        if pairs.flags & FLAG_MAPPED != 0 {
+            let idx = pairs.map_idx[slot] + value as usize;
+            value = match pairs.flags & FLAG_WIDE != 0 {
+                true => le16(&self.data, self.map + 2 * idx)? as i32,
+                false => byte(&self.data, self.map + idx)? as i32,
+            };
+        }
+
+        let plies = match wdl {
+            Wdl::Win => pairs.flags & FLAG_WIN_PLIES != 0,
+            Wdl::Loss => pairs.flags & FLAG_LOSS_PLIES != 0,
+            _ => false,
+        };
+        if !plies {
+            value *= 2;
+        }
+
+        Some(value + 1)
+    }
+
+    // Value number `idx` of the table
+    fn decompress(&self, pairs: &Pairs, idx: u64) -> Option<i32> {
+        let data = &self.data;
+        if pairs.flags & FLAG_SINGLE_VALUE != 0 {
+            return Some(pairs.min_sym_len as i32);
+        }
+
+        // The sparse index points into the block list near `idx`, walk from
+        // there to the block holding it
+        let k = (idx / pairs.span) as usize;
+        let mut block = le32(data, pairs.sparse_index + 6 * k)? as usize;
+        let mut offset = le16(data, pairs.sparse_index + 6 * k + 4)? as i64;
+        offset += (idx % pairs.span) as i64 - (pairs.span / 2) as i64;
+
+        let block_length = |b: usize| le16(data, pairs.block_length + 2 * b).map(|len| len as i64);
+        while offset < 0 {
+            block = block.checked_sub(1)?;
+            offset += block_length(block)? + 1;
+        }
+        while offset > block_length(block)? {
+            offset -= block_length(block)? + 1;
+            block += 1;
+        }
+
+        let mut ptr = pairs.data + block * pairs.size_of_block as usize;
+        let mut buf = be64(data, ptr)?;
+        let mut buf_size = 64;
+        ptr += 8;
+        let min_len = pairs.min_sym_len as usize;
+
+        // Symbols are canonical Huffman codes, longer ones are smaller
+        let mut sym = loop {
+            let mut len = 0;
+            while buf < *pairs.base64.get(len)? {
+                len += 1;
+            }
+
+            let sym = (buf - pairs.base64[len]).checked_shr((64 - len - min_len) as u32).unwrap_or(0) as usize
+                + le16(data, pairs.lowest_sym + 2 * len)? as usize;
+            let run = *pairs.sym_len.get(sym)? as i64 + 1;
+            if offset < run {
+                break sym;
+            }
+
+            offset -= run;
+            buf <<= len + min_len;
+            buf_size -= len + min_len;
+            if buf_size <= 32 {
+                buf_size += 32;
+                buf |= (be32(data, ptr)? as u64) << (64 - buf_size);
+                ptr += 4;
+            }
+        };
+
+        // Each symbol stands for a pair of symbols, down to single values
+        while *pairs.sym_len.get(sym)? != 0 {
+            let (left, right) = btree(data, pairs.btree, sym)?;
+            let run = *pairs.sym_len.get(left)? as i64 + 1;
+
+            match offset < run {
+                true => sym = left,
+                false => {
+                    offset -= run;
+                    sym = right;
+                }
+            }
+        }
+
+        btree(data, pairs.btree, sym).map(|(left, _)| left as i32)
+    }
+}
+
+// Children of a symbol, 12 bits each
+fn btree(data: &[u8], btree: usize, sym: usize) -> Option<(usize, usize)> {
+    let lr = data.get(btree + 3 * sym..btree + 3 * sym + 3)?;
+    let left = ((lr[1] as usize & 0xf) << 8) | lr[0] as usize;
+    let right = ((lr[2] as usize) << 4) | (lr[1] as usize >> 4);
+
+    Some((left, right))
+}
+
+// Split the pieces into the groups they are indexed by, and work out the
+// index size of each group in the order the table was encoded
+fn set_groups(
+    pairs: &mut Pairs,
+    pieces: usize,
+    has_pawns: bool,
+    unique: bool,
+    pawn_count: [usize; 2],
+    order: [u8; 2],
+    file: usize,
+) {
+    let m = maps();
+    let mut first_len: i32 = match (has_pawns, unique) {
+        (true, _) => 0,
+        (false, true) => 3,
+        (false, false) => 2,
+    };
+
+    let mut n = 0;
+    pairs.group_len[0] = 1;
+    for i in 1..pieces {
+        first_len -= 1;
+        if first_len > 0 || pairs.pieces[i] == pairs.pieces[i - 1] {
+            pairs.group_len[n] += 1;
+        } else {
+            n += 1;
+            pairs.group_len[n] = 1;
+        }
+    }
+    n += 1;
+    pairs.group_len[n] = 0;
+
+    let both = has_pawns && pawn_count[1] > 0;
+    let mut next = match both {
+        true => 2,
+        false => 1,
+    };
+    let mut free = 64 - pairs.group_len[0] - if both { pairs.group_len[1] } else { 0 };
+    let mut idx: u64 = 1;
+
+    let mut k = 0;
+    while next < n || k == order[0] as usize || k == order[1] as usize {
+        if k == order[0] as usize {
+            pairs.group_idx[0] = idx;
+            idx *= match (has_pawns, unique) {
+                (true, _) => m.lead_pawns_size[pairs.group_len[0]][file],
+                (false, true) => 31_332,
+                (false, false) => 462,
+            };
+        } else if k == order[1] as usize {
+            pairs.group_idx[1] = idx;
+            idx *= m.binomial[pairs.group_len[1]][48 - pairs.group_len[0]];
+        } else {
+            pairs.group_idx[next] = idx;
+            idx *= m.binomial[pairs.group_len[next]][free];
+            free -= pairs.group_len[next];
+            next += 1;
+        }
+        k += 1;
+    }
+    pairs.group_idx[n] = idx;
+}
+
+// Read the Huffman code of one side, returns the position after it
+fn set_sizes(pairs: &mut Pairs, data: &[u8], mut pos: usize) -> Option<usize> {
+    pairs.flags = byte(data, pos)?;
+    pos += 1;
+
+    if pairs.flags & FLAG_SINGLE_VALUE != 0 {
+        pairs.min_sym_len = byte(data, pos)?;
+        return Some(pos + 1);
+    }
+
+    let groups = pairs.group_len.iter().position(|len| *len == 0)?;
+    let size = pairs.group_idx[groups];
+
+    pairs.size_of_block = 1u64.checked_shl(byte(data, pos)? as u32)?;
+    pairs.span = 1u64.checked_shl(byte(data, pos + 1)? as u32)?;
+    pairs.sparse_index_size = (size + pairs.span - 1) / pairs.span;
+    let padding = byte(data, pos + 2)? as u64;
+    pairs.blocks = le32(data, pos + 3)? as u64;
+    pairs.block_length_size = pairs.blocks + padding;
+    let max_sym_len = byte(data, pos + 7)? as usize;
+    pairs.min_sym_len = byte(data, pos + 8)?;
+    pos += 9;
+    pairs.lowest_sym = pos;
+
+    // Smallest code of each length, left aligned in 64 bits
+    let min_len = pairs.min_sym_len as usize;
+    let lengths = (max_sym_len + 1).checked_sub(min_len)?;
+    let mut base64 = vec![0u64; lengths];
+    for i in (0..lengths.saturating_sub(1)).rev() {
+        let lowest = le16(data, pos + 2 * i)? as u64;
+        let next = le16(data, pos + 2 * i + 2)? as u64;
+        base64[i] = (base64[i + 1].wrapping_add(lowest).wrapping_sub(next)) / 2;
+    }
+    for (i, base) in base64.iter_mut().enumerate() {
+        *base = base.checked_shl((64 - i - min_len) as u32).unwrap_or(0);
+    }
+    pairs.base64 = base64;
+    pos += lengths * 2;
+
+    let symbols = le16(data, pos)? as usize;
+    pos += 2;
+    pairs.btree = pos;
+
+    // Values each symbol stands for, less one
+    pairs.sym_len = vec![0; symbols];
+    let mut visited = vec![false; symbols];
+    for sym in 0..symbols {
+        if !visited[sym] {
+            pairs.sym_len[sym] = sym_len(pairs, data, sym, &mut visited)?;
+        }
+    }
+
+    Some(pos + symbols * 3 + (symbols & 1))
+}
+
+fn sym_len(pairs: &mut Pairs, data: &[u8], sym: usize, visited: &mut [bool]) -> Option<u8> {
+    *visited.get_mut(sym)? = true;
+
+    let (left, right) = btree(data, pairs.btree, sym)?;
+    if right == 0xfff {
+        return Some(0);
+    }
+
+    if !*visited.get(left)? {
+        pairs.sym_len[left] = sym_len(pairs, data, left, visited)?;
+    }
+    if !*visited.get(right)? {
+        pairs.sym_len[right] = sym_len(pairs, data, right, visited)?;
+    }
+
+    Some(pairs.sym_len[left].wrapping_add(pairs.sym_len[right]).wrapping_add(1))
+}
+
+#[cfg(test)]
+mod tests {
+    use std::path::PathBuf;
+
+    use super::*;
+    use crate::variant::Variant;
+
+    // No tables ship with the tree. The probe tests below are ignored and
+    // only run, with `cargo test -- --ignored`, once KQvK, KRvK and KPvK
+    // (.rtbw and .rtbz) from the standard Syzygy set are copied here
+    fn fixtures() -> PathBuf {
+        Path::new(env!("CARGO_MANIFEST_DIR")).join("tests/fixtures/syzygy")
+    }
+
+    fn load_fixtures() -> Arc<Tablebases> {
+        let found = init(&fixtures()).expect("fixture table directory");
+        assert_eq!(found, 6, "expected the six fixture tables in {}", fixtures().display());
+        tablebases().unwrap()
+    }
+
+    fn near(a: usize, b: usize) -> bool {
+        (file_of(a) as i32 - file_of(b) as i32).abs() <= 1 && (rank_of(a) as i32 - rank_of(b) as i32).abs() <= 1
+    }
+
+    #[test]
+    fn map_kk_codes_every_king_pair_once() {
+        let m = maps();
+        let mut codes = Vec::new();
+
+        // First king in the a1-d1-d4 triangle, and when it is on the
+        // diagonal the second one on or below it
+        for s1 in (0..64).filter(|s| file_of(*s) <= 3 && off_diagonal(*s) <= 0) {
+            for s2 in (0..64).filter(|s| !near(s1, *s)) {
+                if off_diagonal(s1) == 0 && off_diagonal(s2) > 0 {
+                    continue;
+                }
+                codes.push((m.map_kk[m.map_a1d1d4[s1] as usize][s2], s1, s2));
+            }
+        }
+
+        codes.sort_unstable();
+        assert_eq!(codes.len(), 462);
+        for (expected, (code, s1, s2)) in codes.iter().enumerate() {
+            assert_eq!(*code, expected as u64, "kings on {} and {}", s1, s2);
+        }
+    }
+
+    #[test]
+    fn lead_pawn_idx_codes_every_pawn_set_once() {
+        let m = maps();
+
+        for lead in 1..=3 {
+            for file in 0..4 {
+                let mut codes = Vec::new();
+
+                // The leading pawn is the one with the highest map_pawns,
+                // the others come from the squares below it
+                for rank in 1..7 {
+                    let first = rank * 8 + file;
+                    let below: Vec<usize> = (8..56).filter(|s| m.map_pawns[*s] < m.map_pawns[first]).collect();
+
+                    for others in combinations(&below, lead - 1) {
+                        let mut others = others;
+                        others.sort_by_key(|sq| m.map_pawns[*sq]);
+
+                        let mut idx = m.lead_pawn_idx[lead][first];
+                        for (i, sq) in others.iter().enumerate() {
+                            idx += m.binomial[i + 1][m.map_pawns[*sq] as usize];
+                        }
+                        codes.push(idx);
+                    }
+                }
+
+                codes.sort_unstable();
+                assert_eq!(codes.len() as u64, m.lead_pawns_size[lead][file], "{} pawns, file {}", lead, file);
+                for (expected, code) in codes.iter().enumerate() {
+                    assert_eq!(*code, expected as u64, "{} pawns, file {}", lead, file);
+                }
+            }
+        }
+    }
+
+    fn combinations(squares: &[usize], k: usize) -> Vec<Vec<usize>> {
+        if k == 0 {
+            return vec![Vec::new()];
+        }
+
+        let mut all = Vec::new();
+        for (i, sq) in squares.iter().enumerate() {
+            for mut rest in combinations(&squares[i + 1..], k - 1) {
+                rest.push(*sq);
+                all.push(rest);
+            }
+        }
+        all
+    }
+
+    // Plies to mate of every king and piece against king position by
+    // retrograde analysis, indexed by white king, piece and black king.
+    // `white` holds the wins with white to move, `black` the losses with
+    // black to move, None for draws and impossible positions
+    struct Solved {
+        white: Vec<Option<i32>>,
+        black: Vec<Option<i32>>,
+    }
+
+    const KING_STEPS: [(i32, i32); 8] = [(-1, -1), (-1, 0), (-1, 1), (0, -1), (0, 1), (1, -1), (1, 0), (1, 1)];
+
+    fn step(sq: usize, (dx, dy): (i32, i32)) -> Option<usize> {
+        let (x, y) = (file_of(sq) as i32 + dx, rank_of(sq) as i32 + dy);
+        ((0..8).contains(&x) && (0..8).contains(&y)).then(|| (y * 8 + x) as usize)
+    }
+
+    // Squares the piece reaches, stopping at `blocker`
+    fn slides(from: usize, dirs: &[(i32, i32)], blocker: usize) -> Vec<usize> {
+        let mut squares = Vec::new();
+        for dir in dirs {
+            let mut sq = from;
+            while let Some(next) = step(sq, *dir) {
+                squares.push(next);
+                if next == blocker {
+                    break;
+                }
+                sq = next;
+            }
+        }
+        squares
+    }
+
+    fn solve(dirs: &[(i32, i32)]) -> Solved {
+        let index = |wk: usize, wx: usize, bk: usize| (wk * 64 + wx) * 64 + bk;
+        let possible = |wk: usize, wx: usize, bk: usize| wk != wx && wx != bk && !near(wk, bk);
+        let attacked = |wk: usize, wx: usize, sq: usize| near(wk, sq) || slides(wx, dirs, wk).contains(&sq);
+
+        let mut white = vec![None; 64 * 64 * 64];
+        let mut black = vec![None; 64 * 64 * 64];
+
+        // Black's replies, None when one takes the piece and draws
+        let replies = |wk: usize, wx: usize, bk: usize| -> Option<Vec<usize>> {
+            let mut to = Vec::new();
+            for sq in KING_STEPS.iter().filter_map(|d| step(bk, *d)) {
+                if sq == wx && !near(wk, wx) {
+                    return None;
+                }
+                if sq != wx && !attacked(wk, wx, sq) {
+                    to.push(index(wk, wx, sq));
+                }
+            }
+            Some(to)
+        };
+
+        for (wk, wx, bk) in (0..64 * 64 * 64).map(|i| (i / 4096, i / 64 % 64, i % 64)) {
+            let checked = attacked(wk, wx, bk);
+            if possible(wk, wx, bk) && checked && matches!(replies(wk, wx, bk), Some(r) if r.is_empty()) {
+                black[index(wk, wx, bk)] = Some(0);
+            }
+        }
+
+        for plies in 1..64 {
+            let mut changed = false;
+
+            for (wk, wx, bk) in (0..64 * 64 * 64).map(|i| (i / 4096, i / 64 % 64, i % 64)) {
+                let i = index(wk, wx, bk);
+                if !possible(wk, wx, bk) {
+                    continue;
+                }
+
+                if plies % 2 == 1 && white[i].is_none() && !attacked(wk, wx, bk) {
+                    let king = KING_STEPS.iter().filter_map(|d| step(wk, *d)).filter(|sq| *sq != wx && !near(*sq, bk));
+                    let king = king.map(|sq| index(sq, wx, bk));
+                    let piece = slides(wx, dirs, wk).into_iter().filter(|sq| *sq != bk).map(|sq| index(wk, sq, bk));
+
+                    if king.chain(piece).any(|j| black[j] == Some(plies - 1)) {
+                        white[i] = Some(plies);
+                        changed = true;
+                    }
+                }
+
+                if plies % 2 == 0 && black[i].is_none() {
+                    if let Some(replies) = replies(wk, wx, bk).filter(|r| !r.is_empty()) {
+                        if replies.iter().all(|j| white[*j].is_some()) {
+                            black[i] = Some(plies);
+                            changed = true;
+                        }
+                    }
+                }
+            }
+
+            if !changed && plies % 2 == 0 {
+                break;
+            }
+        }
+
+        Solved { white, black }
+    }
+
+    fn state_of(pieces: &[(usize, char)], black_to_move: bool) -> BoardState {
+        let mut board = ['1'; 64];
+        for (sq, pc) in pieces {
+            board[*sq] = *pc;
+        }
+
+        let ranks: Vec<String> = (0..8).rev().map(|rank| board[rank * 8..rank * 8 + 8].iter().collect()).collect();
+        let side = if black_to_move { "b" } else { "w" };
+        BoardState::from_fen(&format!("{} {} - - 0 1", ranks.join("/"), side), Variant::standard()).unwrap()
+    }
+
+    // Every 97th position, so the test finishes in reasonable time
+    fn check_against(tbs: &Tablebases, piece: char, solved: &Solved) {
+        for i in (0..64 * 64 * 64).step_by(97) {
+            let (wk, wx, bk) = (i / 4096, i / 64 % 64, i % 64);
+            if wk == wx || wx == bk || near(wk, bk) {
+                continue;
+            }
+
+            for black_to_move in [false, true].iter() {
+                let state = state_of(&[(wk, 'K'), (wx, piece), (bk, 'k')], *black_to_move);
+                if state.b_check && !black_to_move {
+                    continue;
+                }
+
+                let (expected, plies) = match black_to_move {
+                    false => (solved.white[i].map_or(Wdl::Draw, |_| Wdl::Win), solved.white[i]),
+                    true => (solved.black[i].map_or(Wdl::Draw, |_| Wdl::Loss), solved.black[i]),
+                };
+
+                let fen = state.to_fen();
+                assert_eq!(probe_wdl(&state), Some(expected), "{}", fen);
+
+                // DTZ tables kept in moves may come out one ply long
+                let dtz = dtz(tbs, &state).unwrap_or_else(|| panic!("no DTZ for {}", fen));
+                match plies {
+                    Some(p) if !black_to_move => assert!(dtz == p || dtz == p + 1, "{}: DTZ {} for mate in {} plies", fen, dtz, p),
+                    Some(p) => assert!(-dtz == p.max(1) || -dtz == p + 1, "{}: DTZ {} for mate in {} plies", fen, dtz, p),
+                    None => assert_eq!(dtz, 0, "{}", fen),
+                }
+            }
+        }
+    }
+
+    const ROOK: [(i32, i32); 4] = [(-1, 0), (1, 0), (0, -1), (0, 1)];
+    // Same directions as the king, but sliding
+    const QUEEN: [(i32, i32); 8] = KING_STEPS;
+
+    #[test]
+    #[ignore = "needs KQvK, KRvK and KPvK tables copied to tests/fixtures/syzygy"]
+    fn kqvk_and_krvk_match_retrograde_analysis() {
+        let tbs = load_fixtures();
+
+        let queen = solve(&QUEEN);
+        assert_eq!(queen.white.iter().flatten().max(), Some(&19));
+        check_against(&tbs, 'Q', &queen);
+
+        let rook = solve(&ROOK);
+        assert_eq!(rook.white.iter().flatten().max(), Some(&31));
+        check_against(&tbs, 'R', &rook);
+    }
+
+    #[test]
+    #[ignore = "needs KQvK, KRvK and KPvK tables copied to tests/fixtures/syzygy"]
+    fn kpvk_known_positions() {
+        let tbs = load_fixtures();
+        let probe = |fen: &str| {
+            let state = BoardState::from_fen(fen, Variant::standard()).unwrap();
+            (probe_wdl(&state), dtz(&tbs, &state), root_move(&state))
+        };
+        let played = |fen: &str, text: &str| {
+            let state = BoardState::from_fen(fen, Variant::standard()).unwrap();
+            Move::parse(text, 8).and_then(|mv| rules::resolve_move(mv, &state))
+        };
+
+        // King on the sixth rank in front of its pawn wins whoever moves
+        assert_eq!(probe("3k4/8/3K4/3P4/8/8/8/8 w - - 0 1").0, Some(Wdl::Win));
+        assert_eq!(probe("3k4/8/3K4/3P4/8/8/8/8 b - - 0 1").0, Some(Wdl::Loss));
+
+        // A rook pawn with the defending king in the corner is drawn
+        let (wdl, dtz, _) = probe("k7/8/1K6/P7/8/8/8/8 w - - 0 1");
+        assert_eq!((wdl, dtz), (Some(Wdl::Draw), Some(0)));
+        assert_eq!(probe("k7/8/1K6/P7/8/8/8/8 b - - 0 1").0, Some(Wdl::Draw));
+
+        // Promoting wins at once, for either colour
+        let fen = "8/4P3/8/8/8/8/8/K6k w - - 0 1";
+        let (wdl, dtz, root) = probe(fen);
+        assert_eq!((wdl, dtz), (Some(Wdl::Win), Some(1)));
+        assert_eq!(root, played(fen, "e7e8q").map(|mv| (mv, 1)));
+
+        let fen = "k6K/8/8/8/8/8/4p3/8 b - - 0 1";
+        let (wdl, dtz, root) = probe(fen);
+        assert_eq!((wdl, dtz), (Some(Wdl::Win), Some(1)));
+        assert_eq!(root, played(fen, "e2e1q").map(|mv| (mv, 1)));
+
+        // Losing side to move with the pawn about to queen
+        let (wdl, dtz, _) = probe("8/4P3/8/8/8/8/8/K6k b - - 0 1");
+        assert_eq!(wdl, Some(Wdl::Loss));
+        assert!(matches!(dtz, Some(d) if d < 0));
+    }
+}
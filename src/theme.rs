@@ -0,0 +1,194 @@
+use std::fs;
+use std::path::{Path, PathBuf};
+
+use ggez::{graphics::Color, GameError, GameResult};
+use serde::Deserialize;
+
+// Colors used to draw the board, highlights and side panel text
+#[derive(Clone)]
+pub struct Theme {
+    pub name: String,
+    pub light: Color,
+    pub dark: Color,
+    pub hill_light: Color,
+    pub hill_dark: Color,
+    pub selection: Color,
+    pub move_target: Color,
+    pub check: Color,
+    pub text: Color,
+    // File the theme was loaded from, None for built-in themes
+    pub source: Option<PathBuf>,
+}
+
+// On-disk form of a theme, every color is a "#rrggbb" string
+#[derive(Deserialize)]
+struct ThemeFile {
+    name: Option<String>,
+    light: String,
+    dark: String,
+    hill_light: String,
+    hill_dark: String,
+    selection: String,
+    move_target: String,
+    check: String,
+    text: String,
+}
+
+impl Theme {
+    fn from_rgb(name: &str, colors: [(u8, u8, u8); 8]) -> Self {
+        let c = |i: usize| Color::from_rgb(colors[i].0, colors[i].1, colors[i].2);
+
+        Self {
+            name: name.to_string(),
+            light: c(0),
+            dark: c(1),
+            hill_light: c(2),
+            hill_dark: c(3),
+            selection: c(4),
+            move_target: c(5),
+            check: c(6),
+            text: c(7),
+            source: None,
+        }
+    }
+
+    // The themes shipped with the game, the first one is the default
+    pub fn builtin() -> Vec<Theme> {
+        vec![
+            Self::from_rgb(
+                "classic",
+                [
+                    (222, 222, 222),
+                    (40, 40, 40),
+                    (255, 215, 0),
+                    (185, 145, 0),
+                    (255, 85, 85),
+                    (200, 200, 0),
+                    (220, 20, 60),
+                    (255, 0, 0),
+                ],
+            ),
+            Self::from_rgb(
+                "wood",
+                [
+                    (240, 217, 181),
+                    (181, 136, 99),
+                    (255, 215, 0),
+                    (205, 160, 40),
+                    (246, 106, 86),
+                    (205, 210, 106),
+                    (230, 60, 60),
+                    (240, 217, 181),
+                ],
+            ),
+            Self::from_rgb(
+                "green",
+                [
+                    (238, 238, 210),
+                    (118, 150, 86),
+                    (255, 215, 0),
+                    (200, 165, 30),
+                    (246, 106, 86),
+                    (186, 202, 68),
+                    (230, 60, 60),
+                    (238, 238, 210),
+                ],
+            ),
+            // Pure white against dark blue with saturated highlights
+            Self::from_rgb(
+                "high-contrast",
+                [
+                    (255, 255, 255),
+                    (0, 51, 153),
+                    (255, 221, 0),
+                    (204, 153, 0),
+                    (255, 0, 255),
+                    (0, 255, 255),
+                    (255, 0, 0),
+                    (255, 255, 255),
+                ],
+            ),
+            // Okabe-Ito colors, nothing relies on telling red from green
+            Self::from_rgb(
+                "deuteranopia",
+                [
+                    (238, 238, 238),
+                    (0, 114, 178),
+                    (240, 228, 66),
+                    (230, 159, 0),
+                    (204, 121, 167),
+                    (86, 180, 233),
+                    (213, 94, 0),
+                    (240, 228, 66),
+                ],
+            ),
+        ]
+    }
+
+    pub fn by_name(name: &str) -> Option<Theme> {
+        Self::builtin().into_iter().find(|t| t.name == name)
+    }
+
+    // Resolve a settings/command line value, a built-in name or a file path
+    pub fn load(spec: &str) -> GameResult<Theme> {
+        match Self::by_name(spec) {
+            Some(theme) => Ok(theme),
+            None => Self::from_file(Path::new(spec)),
+        }
+    }
+
+    // The value to store in the settings to get this theme back
+    pub fn spec(&self) -> String {
+        match &self.source {
+            Some(path) => path.display().to_string(),
+            None => self.name.clone(),
+        }
+    }
+
+    // Load a theme from a TOML file, named after the file if it has no `name`
+    pub fn from_file(path: &Path) -> GameResult<Theme> {
+        let err = |msg: String| GameError::ResourceLoadError(format!("{}: {}", path.display(), msg));
+
+        let text = fs::read_to_string(path).map_err(|e| err(e.to_string()))?;
+        let file: ThemeFile = toml::from_str(&text).map_err(|e| err(e.to_string()))?;
+
+        let name = match file.name {
+            Some(name) => name,
+            None => path
+                .file_stem()
+                .map(|s| s.to_string_lossy().into_owned())
+                .unwrap_or_else(|| String::from("custom")),
+        };
+
+        Ok(Self {
+            name,
+            light: parse_hex(&file.light).map_err(err)?,
+            dark: parse_hex(&file.dark).map_err(err)?,
+            hill_light: parse_hex(&file.hill_light).map_err(err)?,
+            hill_dark: parse_hex(&file.hill_dark).map_err(err)?,
+            selection: parse_hex(&file.selection).map_err(err)?,
+            move_target: parse_hex(&file.move_target).map_err(err)?,
+            check: parse_hex(&file.check).map_err(err)?,
+            text: parse_hex(&file.text).map_err(err)?,
+            source: Some(path.to_path_buf()),
+        })
+    }
+}
+
+impl Default for Theme {
+    fn default() -> Self {
+        Self::builtin().remove(0)
+    }
+}
+
+fn parse_hex(s: &str) -> Result<Color, String> {
+    let hex = s.trim_start_matches('#');
+
+    if hex.len() != 6 || !hex.chars().all(|c| c.is_ascii_hexdigit()) {
+        return Err(format!("invalid color \"{}\", expected #rrggbb", s));
+    }
+
+    let channel = |i: usize| u8::from_str_radix(&hex[i..i + 2], 16).unwrap();
+
+    Ok(Color::from_rgb(channel(0), channel(2), channel(4)))
+}
@@ -0,0 +1,141 @@
+use std::mem;
+use std::sync::Mutex;
+
+use crate::rules::{self, BoardState, Move, Player, MAX_SIZE};
+
+// Transposition table size unless the settings or the GUI ask for another
+pub const DEFAULT_HASH_MB: usize = 16;
+// The slots are split between this many locks, so the search threads
+// sharing the table seldom wait for each other
+const SHARDS: usize = 1024;
+
+// Kinds of things that go into a position key, each gets its own range of
+// Zobrist keys
+const PIECE_KEYS: u64 = 0;
+const CASTLING_KEYS: u64 = 1 << 16;
+const EN_PASSANT_KEYS: u64 = 2 << 16;
+const POCKET_KEYS: u64 = 3 << 16;
+const CHECK_KEYS: u64 = 4 << 16;
+const PROMOTED_KEYS: u64 = 5 << 16;
+const BLACK_TO_MOVE: u64 = 6 << 16;
+const DUCK_TO_MOVE: u64 = 7 << 16;
+
+// Zobrist key number `n`. Scrambling the number gives keys as good as a
+// table of random ones without having to keep such a table around
+fn key(n: u64) -> u64 {
+    let mut z = n.wrapping_add(0x9e37_79b9_7f4a_7c15);
+    z = (z ^ (z >> 30)).wrapping_mul(0xbf58_476d_1ce4_e5b9);
+    z = (z ^ (z >> 27)).wrapping_mul(0x94d0_49bb_1331_11eb);
+    z ^ (z >> 31)
+}
+
+// Zobrist key of everything that decides how the game goes on from here,
+// the move counters left out
+pub fn hash(state: &BoardState) -> u64 {
+    let mut h = 0;
+
+    for (x, y) in rules::squares(state) {
+        let ch = state.board[y as usize][x as usize];
+        if rules::is_piece(ch) {
+            let sq = y as u64 * MAX_SIZE as u64 + x as u64;
+            h ^= key(PIECE_KEYS | (ch as u64) << 8 | sq);
+        }
+    }
+
+    for (idx, file) in state.castling.iter().enumerate() {
+        if let Some(file) = file {
+            h ^= key(CASTLING_KEYS | (idx as u64) << 8 | *file as u64);
+        }
+    }
+
+    if state.enp_w != 0 {
+        h ^= key(EN_PASSANT_KEYS | state.enp_w as u64);
+    }
+    if state.enp_b != 0 {
+        h ^= key(EN_PASSANT_KEYS | 1 << 15 | state.enp_b as u64);
+    }
+
+    for (side, pocket) in state.pockets.iter().enumerate() {
+        for (idx, n) in pocket.iter().enumerate() {
+            if *n > 0 {
+                h ^= key(POCKET_KEYS | (side as u64) << 12 | (idx as u64) << 8 | *n as u64);
+            }
+        }
+    }
+
+    if state.w_checks != 0 || state.b_checks != 0 {
+        h ^= key(CHECK_KEYS | (state.w_checks as u64) << 8 | state.b_checks as u64);
+    }
+
+    if state.promoted != 0 {
+        h ^= key(PROMOTED_KEYS) ^ key(state.promoted as u64) ^ key((state.promoted >> 64) as u64).rotate_left(1);
+    }
+
+    if state.player == Player::Black {
+        h ^= key(BLACK_TO_MOVE);
+    }
+
+    if state.duck_to_move {
+        h ^= key(DUCK_TO_MOVE);
+    }
+
+    h
+}
+
+// What the stored score says about the real one
+#[derive(Clone, Copy, PartialEq, Debug)]
+pub enum Bound {
+    Exact,
+    // At least the score, the search failed high
+    Lower,
+    // At most the score, no move reached alpha
+    Upper,
+}
+
+#[derive(Clone, Copy, Debug)]
+pub struct Entry {
+    pub key: u64,
+    pub mv: Option<Move>,
+    pub score: i32,
+    pub depth: u8,
+    pub bound: Bound,
+}
+
+// Positions already searched, one entry per slot and the newest one kept
+// when two positions share a slot. Shared by all threads of a search
+pub struct Table {
+    shards: Vec<Mutex<Vec<Option<Entry>>>>,
+    per_shard: usize,
+}
+
+impl Table {
+    pub fn new(mb: usize) -> Self {
+        let slots = mb.max(1) * 1024 * 1024 / mem::size_of::<Option<Entry>>();
+        let per_shard = (slots / SHARDS).max(1);
+
+        Self { shards: (0..SHARDS).map(|_| Mutex::new(vec![None; per_shard])).collect(), per_shard }
+    }
+
+    pub fn clear(&self) {
+        for shard in self.shards.iter() {
+            shard.lock().unwrap().iter_mut().for_each(|e| *e = None);
+        }
+    }
+
+    // Shard and slot within it
+    fn slot(&self, key: u64) -> (usize, usize) {
+        let slot = key % (SHARDS * self.per_shard) as u64;
+
+        ((slot % SHARDS as u64) as usize, (slot / SHARDS as u64) as usize)
+    }
+
+    pub fn probe(&self, key: u64) -> Option<Entry> {
+        let (shard, slot) = self.slot(key);
+        self.shards[shard].lock().unwrap()[slot].filter(|e| e.key == key)
+    }
+
+    pub fn store(&self, entry: Entry) {
+        let (shard, slot) = self.slot(entry.key);
+        self.shards[shard].lock().unwrap()[slot] = Some(entry);
+    }
+}
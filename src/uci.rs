@@ -0,0 +1,328 @@
+use std::io::{self, BufRead, Write};
+use std::path::Path;
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::sync::Arc;
+use std::thread::{self, JoinHandle};
+use std::time::Duration;
+
+use crate::book::Book;
+use crate::rules::{self, BoardState, Move, Player};
+use crate::search::{Clock, Info, Limits, Search, MATE, MATE_BOUND, MAX_THREADS};
+use crate::skill::{self, MAX_LEVEL};
+use crate::tablebase;
+use crate::tt::{self, DEFAULT_HASH_MB};
+use crate::variant::Variant;
+
+const MAX_HASH_MB: usize = 4096;
+
+// Universal Chess Interface on stdin/stdout, so the engine can be used by
+// other chess GUIs and tournament managers
+pub struct Uci {
+    // Handed to the search thread while it runs
+    search: Option<Search>,
+    thread: Option<JoinHandle<Search>>,
+    stop: Arc<AtomicBool>,
+    variant: Variant,
+    state: BoardState,
+    // Keys of the positions before `state`, for spotting repetitions
+    history: Vec<u64>,
+    skill: u8,
+    book: Option<Book>,
+    own_book: bool,
+}
+
+impl Uci {
+    pub fn new(hash_mb: usize) -> Self {
+        let search = Search::new(hash_mb);
+        let variant = Variant::standard();
+
+        Self {
+            stop: search.stop_flag(),
+            search: Some(search),
+            thread: None,
+            variant,
+            state: BoardState::new(variant),
+            history: Vec::new(),
+            skill: MAX_LEVEL,
+            book: Book::for_variant(variant),
+            own_book: true,
+        }
+    }
+
+    // Answer commands until "quit" or the end of input
+    pub fn run(&mut self) {
+        let stdin = io::stdin();
+
+        for line in stdin.lock().lines() {
+            let line = match line {
+                Ok(line) => line,
+                Err(_) => break,
+            };
+
+            if !self.command(&line) {
+                break;
+            }
+        }
+
+        self.wait();
+    }
+
+    // Handle one line, false once the engine should exit
+    pub fn command(&mut self, line: &str) -> bool {
+        let mut words = line.split_whitespace();
+
+        match words.next() {
+            Some("uci") => self.identify(),
+            Some("isready") => send("readyok"),
+            Some("setoption") => {
+                self.wait();
+                self.set_option(line);
+            }
+            Some("ucinewgame") => {
+                self.wait();
+                if let Some(search) = &mut self.search {
+                    search.clear();
+                }
+            }
+            Some("position") => {
+                self.wait();
+                if let Err(e) = self.position(words) {
+                    send(&format!("info string {}", e));
+                }
+            }
+            Some("go") => {
+                self.wait();
+                self.go(words);
+            }
+            Some("stop") => {
+                self.stop.store(true, Ordering::Relaxed);
+                self.wait();
+            }
+            Some("quit") => {
+                self.stop.store(true, Ordering::Relaxed);
+                return false;
+            }
+            Some(cmd) => send(&format!("info string Unknown command: {}", cmd)),
+            None => (),
+        }
+
+        true
+    }
+
+    fn identify(&self) {
+        send("id name 2-modded-chess");
+        send("id author rejnhed");
+        send(&format!("option name Hash type spin default {} min 1 max {}", DEFAULT_HASH_MB, MAX_HASH_MB));
+        send(&format!("option name Threads type spin default 1 min 1 max {}", MAX_THREADS));
+
+        let names: Vec<String> = Variant::all().iter().map(|v| format!("var {}", v.protocol_name())).collect();
+        send(&format!("option name UCI_Variant type combo default chess {}", names.join(" ")));
+        send("option name UCI_Chess960 type check default false");
+        send(&format!("option name Skill Level type spin default {} min 0 max {}", MAX_LEVEL, MAX_LEVEL));
+        send("option name OwnBook type check default true");
+        send("option name SyzygyPath type string default <empty>");
+        send("uciok");
+    }
+
+    // "setoption name <name> value <value>", option names are case insensitive
+    fn set_option(&mut self, line: &str) {
+        let rest = line.trim_start_matches("setoption").trim();
+        let rest = rest.strip_prefix("name").unwrap_or(rest).trim();
+        let (name, value) = match rest.split_once(" value ") {
+            Some((name, value)) => (name.trim().to_lowercase(), value.trim()),
+            None => (rest.to_lowercase(), ""),
+        };
+
+        match name.as_str() {
+            "hash" => match value.parse::<usize>() {
+                Ok(mb) if (1..=MAX_HASH_MB).contains(&mb) => {
+                    if let Some(search) = &mut self.search {
+                        search.resize_tt(mb);
+                    }
+                }
+                _ => send(&format!("info string Hash must be 1 to {} MB", MAX_HASH_MB)),
+            },
+            "threads" => match value.parse::<usize>() {
+                Ok(threads) if (1..=MAX_THREADS).contains(&threads) => {
+                    if let Some(search) = &mut self.search {
+                        search.set_threads(threads);
+                    }
+                }
+                _ => send(&format!("info string Threads must be 1 to {}", MAX_THREADS)),
+            },
+            "uci_variant" => match Variant::from_protocol_name(value) {
+                Some(variant) => {
+                    self.variant = Variant { chess960: self.variant.chess960, ..variant };
+                    self.state = BoardState::new(self.variant);
+                    self.history.clear();
+                    self.book = Book::for_variant(self.variant);
+                }
+                None => send(&format!("info string Unknown variant: {}", value)),
+            },
+            "skill level" => match value.parse::<u8>() {
+                Ok(level) if level <= MAX_LEVEL => self.skill = level,
+                _ => send(&format!("info string Skill Level must be 0 to {}", MAX_LEVEL)),
+            },
+            "ownbook" => self.own_book = value == "true",
+            "syzygypath" => {
+                let dir = match value {
+                    "<empty>" => "",
+                    dir => dir,
+                };
+                match tablebase::init(Path::new(dir)) {
+                    Ok(found) if !dir.is_empty() => send(&format!("info string Found {} tablebase files", found)),
+                    Ok(_) => (),
+                    Err(e) => send(&format!("info string {}", e)),
+                }
+            }
+            "uci_chess960" => {
+                self.variant.chess960 = value == "true";
+                self.state.variant.chess960 = self.variant.chess960;
+            }
+            _ => send(&format!("info string Unknown option: {}", name)),
+        }
+    }
+
+    // "position startpos|fen <fen> [moves <move>...]"
+    fn position<'a>(&mut self, mut words: impl Iterator<Item = &'a str>) -> Result<(), String> {
+        let mut state = match words.next() {
+            Some("startpos") => BoardState::new(self.variant),
+            Some("fen") => {
+                let fen: Vec<&str> = words.by_ref().take_while(|w| *w != "moves").collect();
+                BoardState::from_fen(&fen.join(" "), self.variant)?
+            }
+            _ => return Err(String::from("position expects startpos or fen")),
+        };
+
+        let mut history = Vec::new();
+        for text in words.skip_while(|w| *w == "moves") {
+            let mv = Move::parse(text, state.height)
+                .and_then(|mv| rules::resolve_move(mv, &state))
+                .ok_or_else(|| format!("Illegal move: {}", text))?;
+            history.push(tt::hash(&state));
+            state.make_move(mv);
+        }
+
+        self.state = state;
+        self.history = history;
+        Ok(())
+    }
+
+    fn go<'a>(&mut self, mut words: impl Iterator<Item = &'a str>) {
+        let book_move = self.book.as_ref().filter(|_| self.own_book).and_then(|book| book.pick(&self.state));
+        if let Some(mv) = book_move {
+            send(&format!("bestmove {}", uci_move(mv, &self.state, self.variant.chess960)));
+            return;
+        }
+
+        let mut limits = Limits { level: Some(skill::level(self.skill)), ..Limits::default() };
+        let (mut wtime, mut btime, mut winc, mut binc, mut moves_to_go) = (None, None, 0, 0, None);
+
+        while let Some(word) = words.next() {
+            let mut number = || words.next().and_then(|n| n.parse::<i64>().ok()).unwrap_or(0).max(0) as u64;
+
+            match word {
+                "wtime" => wtime = Some(number()),
+                "btime" => btime = Some(number()),
+                "winc" => winc = number(),
+                "binc" => binc = number(),
+                "movestogo" => moves_to_go = Some(number() as u32),
+                "movetime" => limits.movetime = Some(Duration::from_millis(number())),
+                "depth" => limits.depth = Some(number().min(u8::MAX as u64) as u8),
+                "nodes" => limits.nodes = Some(number()),
+                _ => (),
+            }
+        }
+
+        let (time, inc) = match self.state.player {
+            Player::White => (wtime, winc),
+            Player::Black => (btime, binc),
+        };
+        limits.clock = time.map(|ms| Clock {
+            remaining: Duration::from_millis(ms),
+            increment: Duration::from_millis(inc),
+            moves_to_go: moves_to_go.filter(|n| *n > 0),
+        });
+
+        let mut search = match self.search.take() {
+            Some(search) => search,
+            None => return,
+        };
+        search.set_history(self.history.clone());
+        let state = self.state.clone();
+        let chess960 = self.variant.chess960;
+        self.stop.store(false, Ordering::Relaxed);
+
+        self.thread = Some(thread::spawn(move || {
+            let best = search.go(&state, &limits, |info| report(info, &state, chess960));
+
+            match best {
+                Some(mv) => send(&format!("bestmove {}", uci_move(mv, &state, chess960))),
+                None => send("bestmove 0000"),
+            }
+
+            search
+        }));
+    }
+
+    // Let the running search finish and take the searcher back
+    fn wait(&mut self) {
+        if let Some(thread) = self.thread.take() {
+            match thread.join() {
+                Ok(search) => self.search = Some(search),
+                Err(_) => {
+                    eprintln!("Search thread panicked, starting over with an empty table");
+                    let search = Search::new(DEFAULT_HASH_MB);
+                    self.stop = search.stop_flag();
+                    self.search = Some(search);
+                }
+            }
+        }
+    }
+}
+
+pub fn send(line: &str) {
+    let stdout = io::stdout();
+    let mut out = stdout.lock();
+    let _ = writeln!(out, "{}", line);
+    let _ = out.flush();
+}
+
+fn report(info: &Info, state: &BoardState, chess960: bool) {
+    let score = match info.score {
+        s if s >= MATE_BOUND => format!("mate {}", (MATE - s + 1) / 2),
+        s if s <= -MATE_BOUND => format!("mate -{}", (MATE + s) / 2),
+        s => format!("cp {}", s),
+    };
+
+    let mut pos = state.clone();
+    let pv: Vec<String> = info
+        .pv
+        .iter()
+        .map(|mv| {
+            let text = uci_move(*mv, &pos, chess960);
+            pos.make_move(*mv);
+            text
+        })
+        .collect();
+
+    let ms = info.elapsed.as_millis().max(1) as u64;
+    send(&format!(
+        "info depth {} score {} nodes {} nps {} time {} pv {}",
+        info.depth,
+        score,
+        info.nodes,
+        info.nodes * 1000 / ms,
+        ms,
+        pv.join(" ")
+    ));
+}
+
+// Castling is the king taking its own rook in Chess960 and the king's two
+// square step otherwise
+fn uci_move(mv: Move, state: &BoardState, chess960: bool) -> String {
+    match rules::castling_destination(mv, state) {
+        Some(to) if !chess960 => Move::new(mv.from, to).to_text(state.height),
+        _ => mv.to_text(state.height),
+    }
+}
@@ -0,0 +1,339 @@
+use std::fs;
+use std::path::Path;
+use std::sync::OnceLock;
+
+use serde::{Deserialize, Deserializer, Serialize, Serializer};
+
+use crate::betza::{self, Step};
+use crate::pieces;
+use crate::rules::{self, BoardState};
+
+// Rule set a game is played with
+#[derive(Clone, Copy, PartialEq, Debug)]
+pub enum Kind {
+    Standard,
+    // Captures blow up everything but pawns around the capture square
+    Atomic,
+    // Captured pieces change sides and can be dropped back on the board
+    Crazyhouse,
+    // Captures are forced and the side that runs out of moves wins
+    Antichess,
+    // Both kings race to the 8th rank, checks are not allowed
+    RacingKings,
+    // White has a horde of pawns and no king, and loses once they are all gone
+    Horde,
+    // Every turn ends by moving a neutral duck, there is no check
+    Duck,
+    // 10x8 board with an archbishop and a chancellor added to each side
+    Capablanca,
+    // 6x6 board without bishops, pawns don't double-step
+    LosAlamos,
+    // 5x5 minichess with a full set of pieces and no double steps
+    Gardner,
+    // Loaded from the variants file, index into `custom_variants()`
+    Custom(u8),
+}
+
+impl Default for Kind {
+    fn default() -> Self {
+        Kind::Standard
+    }
+}
+
+const BUILTIN_KINDS: [(Kind, &str); 10] = [
+    (Kind::Standard, "standard"),
+    (Kind::Atomic, "atomic"),
+    (Kind::Crazyhouse, "crazyhouse"),
+    (Kind::Antichess, "antichess"),
+    (Kind::RacingKings, "racing-kings"),
+    (Kind::Horde, "horde"),
+    (Kind::Duck, "duck"),
+    (Kind::Capablanca, "capablanca"),
+    (Kind::LosAlamos, "los-alamos"),
+    (Kind::Gardner, "gardner"),
+];
+
+// Custom variants are stored by name so editing the file doesn't mix them up
+impl Serialize for Kind {
+    fn serialize<S: Serializer>(&self, serializer: S) -> Result<S::Ok, S::Error> {
+        let name = match self {
+            Kind::Custom(idx) => custom_variants()[*idx as usize].name.as_str(),
+            kind => BUILTIN_KINDS.iter().find(|(k, _)| k == kind).map(|(_, name)| *name).unwrap(),
+        };
+
+        serializer.serialize_str(name)
+    }
+}
+
+impl<'de> Deserialize<'de> for Kind {
+    fn deserialize<D: Deserializer<'de>>(deserializer: D) -> Result<Self, D::Error> {
+        let name = String::deserialize(deserializer)?;
+
+        if let Some((kind, _)) = BUILTIN_KINDS.iter().find(|(_, n)| *n == name) {
+            return Ok(*kind);
+        }
+
+        // A custom variant may have been removed from the file since
+        match custom_variants().iter().position(|v| v.name == name) {
+            Some(idx) => Ok(Kind::Custom(idx as u8)),
+            None => {
+                eprintln!("Unknown variant \"{}\", playing standard chess", name);
+                Ok(Kind::Standard)
+            }
+        }
+    }
+}
+
+// A variant described in the variants file
+pub struct CustomVariant {
+    pub name: String,
+    pub start_fen: String,
+    // Squares a king wins on, like the hill
+    pub goal_squares: Vec<(u8, u8)>,
+    pub checks_to_win: Option<u8>,
+    // Pieces the king castles with, uppercase
+    pub castling: Vec<char>,
+    pub compulsory_captures: bool,
+    pub compulsory_checks: bool,
+    // Fairy pieces by uppercase letter
+    pub pieces: Vec<(char, Vec<Step>)>,
+}
+
+// On-disk form of a custom variant, fairy pieces map a letter to Betza notation
+#[derive(Deserialize)]
+struct VariantFile {
+    name: String,
+    start_fen: Option<String>,
+    #[serde(default)]
+    goal_squares: Vec<String>,
+    checks_to_win: Option<u8>,
+    castling: Option<Vec<char>>,
+    #[serde(default)]
+    compulsory_captures: bool,
+    #[serde(default)]
+    compulsory_checks: bool,
+    #[serde(default)]
+    pieces: toml::value::Table,
+}
+
+#[derive(Deserialize)]
+struct VariantsFile {
+    #[serde(default)]
+    variant: Vec<VariantFile>,
+}
+
+static CUSTOM_VARIANTS: OnceLock<Vec<CustomVariant>> = OnceLock::new();
+
+pub fn custom_variants() -> &'static [CustomVariant] {
+    CUSTOM_VARIANTS.get().map_or(&[], |v| v.as_slice())
+}
+
+// Read the variants file once at startup, broken entries are skipped with a
+// warning so the rest stay playable
+pub fn load_custom_variants(path: &Path) {
+    let variants = match fs::read_to_string(path) {
+        Ok(text) => match toml::from_str::<VariantsFile>(&text) {
+            Ok(file) => file
+                .variant
+                .into_iter()
+                .filter_map(|v| {
+                    let name = v.name.clone();
+                    CustomVariant::from_file(v)
+                        .map_err(|e| eprintln!("Skipping variant \"{}\" in {}: {}", name, path.display(), e))
+                        .ok()
+                })
+                .take(u8::MAX as usize)
+                .collect(),
+            Err(e) => {
+                eprintln!("Ignoring invalid variants in {}: {}", path.display(), e);
+                Vec::new()
+            }
+        },
+        Err(_) => Vec::new(),
+    };
+
+    let _ = CUSTOM_VARIANTS.set(variants);
+}
+
+impl CustomVariant {
+    fn from_file(file: VariantFile) -> Result<Self, String> {
+        if BUILTIN_KINDS.iter().any(|(_, name)| *name == file.name) {
+            return Err(String::from("name taken by a built-in variant"));
+        }
+
+        let mut pieces = Vec::new();
+        for (letter, betza) in file.pieces.iter() {
+            let mut chars = letter.chars();
+            let pc = match (chars.next(), chars.next()) {
+                (Some(pc), None) if pc.is_ascii_uppercase() && !pieces::is_builtin(pc) => pc,
+                _ => {
+                    return Err(format!(
+                        "piece \"{}\" must be one uppercase letter not used by a built-in piece ({})",
+                        letter,
+                        pieces::builtin_letters()
+                    ))
+                }
+            };
+
+            let betza = betza.as_str().ok_or(format!("piece {} needs a Betza string", pc))?;
+            pieces.push((pc, betza::parse(betza)?));
+        }
+
+        let start_fen = file.start_fen.unwrap_or_else(|| String::from(rules::START_FEN));
+
+        // The variant isn't registered yet, so check the position with its own
+        // pieces standing in as knights
+        let mut stand_in = String::new();
+        for c in start_fen.chars() {
+            let fairy = c.is_ascii_alphabetic() && !pieces::is_builtin(c);
+            match (fairy, stand_in.contains(' ')) {
+                (true, false) if pieces.iter().any(|(p, _)| *p == c.to_ascii_uppercase()) => {
+                    stand_in.push(if c.is_ascii_uppercase() { 'N' } else { 'n' })
+                }
+                (true, false) => return Err(format!("start position uses undefined piece {}", c)),
+                _ => stand_in.push(c),
+            }
+        }
+        let board = BoardState::from_fen(&stand_in, Variant::default())?;
+
+        let goal_squares = file
+            .goal_squares
+            .iter()
+            .map(|sq| rules::parse_square(sq, board.height).filter(|(x, _)| *x < board.width).ok_or(format!("bad goal square \"{}\"", sq)))
+            .collect::<Result<Vec<_>, String>>()?;
+
+        Ok(Self {
+            name: file.name,
+            start_fen,
+            goal_squares,
+            checks_to_win: file.checks_to_win.filter(|n| *n > 0),
+            castling: file.castling.unwrap_or_else(|| vec!['R']).iter().map(|c| c.to_ascii_uppercase()).collect(),
+            compulsory_captures: file.compulsory_captures,
+            compulsory_checks: file.compulsory_checks,
+            pieces,
+        })
+    }
+
+    pub fn piece(&self, pc: char) -> Option<&[Step]> {
+        let pc = pc.to_ascii_uppercase();
+        self.pieces.iter().find(|(p, _)| *p == pc).map(|(_, steps)| steps.as_slice())
+    }
+}
+
+// Which extra win conditions are played on top of the normal chess rules
+#[derive(Clone, Copy, PartialEq, Serialize, Deserialize)]
+pub struct Variant {
+    #[serde(default)]
+    pub kind: Kind,
+    pub king_of_the_hill: bool,
+    pub three_check: bool,
+    // Shuffled back rank, works together with the win conditions above
+    #[serde(default)]
+    pub chess960: bool,
+}
+
+impl Variant {
+    const PRESETS: [Variant; 13] = [
+        Variant { kind: Kind::Standard, king_of_the_hill: true, three_check: true, chess960: false },
+        Variant { kind: Kind::Standard, king_of_the_hill: true, three_check: false, chess960: false },
+        Variant { kind: Kind::Standard, king_of_the_hill: false, three_check: true, chess960: false },
+        Variant { kind: Kind::Standard, king_of_the_hill: false, three_check: false, chess960: false },
+        Variant { kind: Kind::Atomic, king_of_the_hill: false, three_check: false, chess960: false },
+        Variant { kind: Kind::Crazyhouse, king_of_the_hill: false, three_check: false, chess960: false },
+        Variant { kind: Kind::Antichess, king_of_the_hill: false, three_check: false, chess960: false },
+        Variant { kind: Kind::RacingKings, king_of_the_hill: false, three_check: false, chess960: false },
+        Variant { kind: Kind::Horde, king_of_the_hill: false, three_check: false, chess960: false },
+        Variant { kind: Kind::Duck, king_of_the_hill: false, three_check: false, chess960: false },
+        Variant { kind: Kind::Capablanca, king_of_the_hill: false, three_check: false, chess960: false },
+        Variant { kind: Kind::LosAlamos, king_of_the_hill: false, three_check: false, chess960: false },
+        Variant { kind: Kind::Gardner, king_of_the_hill: false, three_check: false, chess960: false },
+    ];
+
+    // Plain chess, the rules engine protocols start in
+    pub fn standard() -> Self {
+        Self::PRESETS[3]
+    }
+
+    pub fn name(&self) -> String {
+        let name = match (self.kind, self.king_of_the_hill, self.three_check) {
+            (Kind::Atomic, _, _) => "Atomic",
+            (Kind::Crazyhouse, _, _) => "Crazyhouse",
+            (Kind::Antichess, _, _) => "Antichess",
+            (Kind::RacingKings, _, _) => "Racing Kings",
+            (Kind::Horde, _, _) => "Horde",
+            (Kind::Duck, _, _) => "Duck chess",
+            (Kind::Capablanca, _, _) => "Capablanca chess",
+            (Kind::LosAlamos, _, _) => "Los Alamos chess",
+            (Kind::Gardner, _, _) => "Gardner minichess",
+            (Kind::Custom(idx), _, _) => custom_variants()[idx as usize].name.as_str(),
+            (_, true, true) => "King of the hill + Three-check",
+            (_, true, false) => "King of the hill",
+            (_, false, true) => "Three-check",
+            (_, false, false) => "Standard",
+        };
+
+        match self.chess960 {
+            true => format!("{} (Chess960)", name),
+            false => String::from(name),
+        }
+    }
+
+    // The presets in the order they are listed followed by the custom variants
+    pub fn all() -> Vec<Variant> {
+        let mut all = Self::PRESETS.to_vec();
+        for idx in 0..custom_variants().len() {
+            all.push(Variant { kind: Kind::Custom(idx as u8), king_of_the_hill: false, three_check: false, chess960: false });
+        }
+
+        all
+    }
+
+    // Lowercase name engine protocols know the variant by, e.g. "3check"
+    pub fn protocol_name(&self) -> String {
+        let name = match (self.kind, self.king_of_the_hill, self.three_check) {
+            (Kind::Atomic, _, _) => "atomic",
+            (Kind::Crazyhouse, _, _) => "crazyhouse",
+            (Kind::Antichess, _, _) => "antichess",
+            (Kind::RacingKings, _, _) => "racingkings",
+            (Kind::Horde, _, _) => "horde",
+            (Kind::Duck, _, _) => "duck",
+            (Kind::Capablanca, _, _) => "capablanca",
+            (Kind::LosAlamos, _, _) => "losalamos",
+            (Kind::Gardner, _, _) => "gardner",
+            (Kind::Custom(idx), _, _) => return custom_variants()[idx as usize].name.to_lowercase().replace(' ', "-"),
+            (_, true, true) => "kingofthehill-3check",
+            (_, true, false) => "kingofthehill",
+            (_, false, true) => "3check",
+            (_, false, false) => "chess",
+        };
+
+        String::from(name)
+    }
+
+    pub fn from_protocol_name(name: &str) -> Option<Variant> {
+        let name = name.to_lowercase();
+        Self::all().into_iter().find(|v| v.protocol_name() == name)
+    }
+
+    // Cycle through `all`, Chess960 is toggled separately and kept as it is
+    pub fn next(&self) -> Variant {
+        let all = Self::all();
+
+        let plain = Variant { chess960: false, ..*self };
+        let idx = all.iter().position(|v| *v == plain).unwrap_or(0);
+        Variant { chess960: self.chess960, ..all[(idx + 1) % all.len()] }
+    }
+
+    pub fn custom(&self) -> Option<&'static CustomVariant> {
+        match self.kind {
+            Kind::Custom(idx) => custom_variants().get(idx as usize),
+            _ => None,
+        }
+    }
+}
+
+impl Default for Variant {
+    fn default() -> Self {
+        Self::PRESETS[0]
+    }
+}
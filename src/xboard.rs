@@ -0,0 +1,488 @@
+use std::io::{self, BufRead};
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::sync::{Arc, Mutex};
+use std::thread::{self, JoinHandle};
+use std::time::Duration;
+
+use crate::book::Book;
+use crate::rules::{self, BoardState, Move, Outcome, Player};
+use crate::search::{self, Clock, Info, Limits, Search, MATE, MATE_BOUND, MAX_THREADS};
+use crate::tt::DEFAULT_HASH_MB;
+use crate::uci::send;
+use crate::variant::{Kind, Variant};
+
+// XBoard shows mates as this plus the moves to mate
+const XBOARD_MATE: i32 = 100_000;
+
+// The engine's search for its move, which sends the move itself unless it
+// was cancelled first
+struct Thinking {
+    thread: JoinHandle<(Search, Option<Move>)>,
+    cancelled: Arc<Mutex<bool>>,
+}
+
+// Chess Engine Communication Protocol (version 2) as spoken by XBoard and
+// WinBoard, the engine keeps the game and moves when it is its turn
+pub struct Xboard {
+    search: Option<Search>,
+    thinking: Option<Thinking>,
+    stop: Arc<AtomicBool>,
+    variant: Variant,
+    start: BoardState,
+    moves: Vec<Move>,
+    state: BoardState,
+    // Side the engine plays, None in force mode
+    engine: Option<Player>,
+    post: bool,
+    // "level": moves per time control (0 for the whole game), base time
+    // and increment
+    moves_per_control: u32,
+    base: Duration,
+    increment: Duration,
+    // "st": fixed time per move, "sd": depth limit
+    move_time: Option<Duration>,
+    depth: Option<u8>,
+    // Engine's clock from "time"
+    remaining: Option<Duration>,
+    book: Option<Book>,
+}
+
+impl Xboard {
+    pub fn new(hash_mb: usize) -> Self {
+        let search = Search::new(hash_mb);
+        let variant = Variant::standard();
+        let state = BoardState::new(variant);
+
+        Self {
+            stop: search.stop_flag(),
+            search: Some(search),
+            thinking: None,
+            variant,
+            start: state.clone(),
+            moves: Vec::new(),
+            state,
+            engine: Some(Player::Black),
+            post: false,
+            moves_per_control: 0,
+            base: Duration::from_secs(300),
+            increment: Duration::ZERO,
+            move_time: None,
+            depth: None,
+            remaining: None,
+            book: Book::for_variant(variant),
+        }
+    }
+
+    // Answer commands until "quit" or the end of input
+    pub fn run(&mut self) {
+        let stdin = io::stdin();
+
+        for line in stdin.lock().lines() {
+            let line = match line {
+                Ok(line) => line,
+                Err(_) => break,
+            };
+
+            if !self.command(&line) {
+                break;
+            }
+        }
+
+        self.finish(true);
+    }
+
+    // Handle one line, false once the engine should exit
+    pub fn command(&mut self, line: &str) -> bool {
+        let mut words = line.split_whitespace();
+        let cmd = match words.next() {
+            Some(cmd) => cmd,
+            None => return true,
+        };
+        let arg = words.next();
+
+        match cmd {
+            "xboard" | "accepted" | "rejected" | "random" | "hard" | "easy" | "computer" | "name" | "rating" | "ics"
+            | "otim" | "draw" => (),
+            "protover" => self.features(),
+            "ping" => send(&format!("pong {}", arg.unwrap_or(""))),
+            "post" => self.post = true,
+            "nopost" => self.post = false,
+            "quit" => {
+                self.finish(true);
+                return false;
+            }
+            // Move now
+            "?" => {
+                self.stop.store(true, Ordering::Relaxed);
+                self.finish(false);
+            }
+            "new" => {
+                self.finish(true);
+                self.variant = Variant::standard();
+                self.set_position(BoardState::new(self.variant));
+                self.engine = Some(Player::Black);
+                self.move_time = None;
+                self.depth = None;
+                if let Some(search) = &mut self.search {
+                    search.clear();
+                }
+            }
+            "variant" => {
+                self.finish(true);
+                match arg.and_then(from_xboard_name) {
+                    Some(variant) => {
+                        self.variant = variant;
+                        self.set_position(BoardState::new(variant));
+                    }
+                    None => send(&format!("Error (unknown variant): {}", arg.unwrap_or(""))),
+                }
+            }
+            "setboard" => {
+                self.finish(true);
+                let fen = line.trim_start().trim_start_matches("setboard").trim();
+                match BoardState::from_fen(fen, self.variant) {
+                    Ok(state) => self.set_position(state),
+                    Err(e) => send(&format!("tellusererror Illegal position: {}", e)),
+                }
+            }
+            "force" | "result" => {
+                self.finish(true);
+                self.engine = None;
+            }
+            "go" => {
+                self.finish(true);
+                self.engine = Some(self.state.player);
+                self.think();
+            }
+            "playother" => {
+                self.finish(true);
+                self.engine = Some(self.state.player.switch());
+            }
+            "white" | "black" => {
+                self.finish(true);
+                self.state.player = match cmd {
+                    "white" => Player::White,
+                    _ => Player::Black,
+                };
+                self.engine = Some(self.state.player.switch());
+            }
+            "usermove" => match arg {
+                Some(text) => self.user_move(text),
+                None => send("Error (no move): usermove"),
+            },
+            "undo" | "remove" => {
+                self.finish(true);
+                let plies = match cmd {
+                    "undo" => 1,
+                    _ => 2,
+                };
+                let kept = self.moves.len().saturating_sub(plies);
+                self.moves.truncate(kept);
+                self.replay();
+            }
+            "level" => self.level(line),
+            "st" => match arg.and_then(|n| n.parse::<f64>().ok()) {
+                Some(secs) if secs > 0. => self.move_time = Some(Duration::from_secs_f64(secs)),
+                _ => send(&format!("Error (bad time): {}", line)),
+            },
+            "sd" => match arg.and_then(|n| n.parse::<u8>().ok()) {
+                Some(depth) => self.depth = Some(depth.max(1)),
+                None => send(&format!("Error (bad depth): {}", line)),
+            },
+            // Centiseconds on the engine's clock
+            "time" => {
+                if let Some(cs) = arg.and_then(|n| n.parse::<i64>().ok()) {
+                    self.remaining = Some(Duration::from_millis(cs.max(0) as u64 * 10));
+                }
+            }
+            "memory" => {
+                if let Some(mb) = arg.and_then(|n| n.parse::<usize>().ok()) {
+                    self.finish(true);
+                    if let Some(search) = &mut self.search {
+                        search.resize_tt(mb.max(1));
+                    }
+                }
+            }
+            "cores" => {
+                if let Some(threads) = arg.and_then(|n| n.parse::<usize>().ok()) {
+                    self.finish(true);
+                    if let Some(search) = &mut self.search {
+                        search.set_threads(threads.min(MAX_THREADS));
+                    }
+                }
+            }
+            // Protocol version 1 sends bare moves
+            _ => match Move::parse(cmd, self.state.height).or_else(|| castling(cmd, &self.state)) {
+                Some(_) => self.user_move(cmd),
+                None => send(&format!("Error (unknown command): {}", cmd)),
+            },
+        }
+
+        true
+    }
+
+    // Duck chess is left out, a turn there is two moves
+    fn features(&self) {
+        let mut variants: Vec<String> =
+            Variant::all().iter().filter(|v| v.kind != Kind::Duck).map(xboard_name).collect();
+        variants.push(String::from("fischerandom"));
+
+        send(&format!(
+            "feature myname=\"2-modded-chess\" ping=1 setboard=1 playother=1 usermove=1 san=0 time=1 draw=0 sigint=0 \
+             sigterm=0 reuse=1 analyze=0 colors=0 memory=1 smp=1 variants=\"{}\" done=1",
+            variants.join(",")
+        ));
+    }
+
+    fn set_position(&mut self, state: BoardState) {
+        self.book = Book::for_variant(state.variant);
+        self.start = state.clone();
+        self.state = state;
+        self.moves.clear();
+    }
+
+    fn replay(&mut self) {
+        let mut state = self.start.clone();
+        for mv in self.moves.iter() {
+            state.make_move(*mv);
+        }
+        self.state = state;
+    }
+
+    fn play(&mut self, mv: Move) {
+        self.moves.push(mv);
+        self.state.make_move(mv);
+    }
+
+    fn user_move(&mut self, text: &str) {
+        self.finish(false);
+
+        let mv = Move::parse(text, self.state.height)
+            .and_then(|mv| rules::resolve_move(mv, &self.state))
+            .or_else(|| castling(text, &self.state));
+
+        match mv {
+            Some(mv) => {
+                self.play(mv);
+                match game_result(&self.state) {
+                    Some(result) => send(&result),
+                    None => self.think(),
+                }
+            }
+            None => send(&format!("Illegal move: {}", text)),
+        }
+    }
+
+    // "level 40 5 0": 40 moves in 5 minutes, no increment. The base can be
+    // minutes:seconds and the increment a fraction of a second
+    fn level(&mut self, line: &str) {
+        let words: Vec<&str> = line.split_whitespace().skip(1).collect();
+        if words.len() != 3 {
+            send(&format!("Error (bad level): {}", line));
+            return;
+        }
+
+        let base = match words[1].split_once(':') {
+            Some((min, sec)) => min.parse::<u64>().ok().zip(sec.parse::<u64>().ok()).map(|(m, s)| m * 60 + s),
+            None => words[1].parse::<u64>().ok().map(|m| m * 60),
+        };
+
+        match (words[0].parse::<u32>(), base, words[2].parse::<f64>()) {
+            (Ok(mps), Some(base), Ok(inc)) if inc >= 0. => {
+                self.moves_per_control = mps;
+                self.base = Duration::from_secs(base);
+                self.increment = Duration::from_secs_f64(inc);
+                self.remaining = None;
+                self.move_time = None;
+            }
+            _ => send(&format!("Error (bad level): {}", line)),
+        }
+    }
+
+    fn limits(&self) -> Limits {
+        if let Some(time) = self.move_time {
+            return Limits { movetime: Some(time), depth: self.depth, ..Limits::default() };
+        }
+
+        // Moves left until the next time control
+        let moves_to_go = match self.moves_per_control {
+            0 => None,
+            mps => Some(mps - (self.state.fullmove.saturating_sub(1) as u32 % mps)),
+        };
+
+        Limits {
+            depth: self.depth,
+            clock: Some(Clock {
+                remaining: self.remaining.unwrap_or(self.base),
+                increment: self.increment,
+                moves_to_go,
+            }),
+            ..Limits::default()
+        }
+    }
+
+    // Start searching when it is the engine's turn
+    fn think(&mut self) {
+        if self.engine != Some(self.state.player) || rules::outcome(&self.state).is_some() {
+            return;
+        }
+
+        let state = self.state.clone();
+        let cancelled = Arc::new(Mutex::new(false));
+
+        if let Some(mv) = self.book.as_ref().and_then(|book| book.pick(&state)) {
+            send(&format!("move {}", xboard_move(mv, &state)));
+            self.play(mv);
+            if let Some(result) = game_result(&self.state) {
+                send(&result);
+            }
+            return;
+        }
+
+        let mut search = match self.search.take() {
+            Some(search) => search,
+            None => return,
+        };
+        search.set_history(search::game_keys(&self.start, &self.moves));
+        let limits = self.limits();
+        let post = self.post;
+        let flag = Arc::clone(&cancelled);
+        self.stop.store(false, Ordering::Relaxed);
+
+        let thread = thread::spawn(move || {
+            let best = search.go(&state, &limits, |info| {
+                if post {
+                    report(info, &state);
+                }
+            });
+
+            // Sent with the lock held, so a cancel either comes first or
+            // finds the move already played
+            let cancelled = flag.lock().unwrap();
+            let played = match (best, *cancelled) {
+                (Some(mv), false) => {
+                    send(&format!("move {}", xboard_move(mv, &state)));
+
+                    let mut next = state.clone();
+                    next.make_move(mv);
+                    if let Some(result) = game_result(&next) {
+                        send(&result);
+                    }
+                    Some(mv)
+                }
+                _ => None,
+            };
+
+            (search, played)
+        });
+
+        self.thinking = Some(Thinking { thread, cancelled });
+    }
+
+    // Wait for the search under way and take in its move, or with `cancel`
+    // stop it and drop the move if it wasn't sent yet
+    fn finish(&mut self, cancel: bool) {
+        let thinking = match self.thinking.take() {
+            Some(thinking) => thinking,
+            None => return,
+        };
+
+        if cancel {
+            *thinking.cancelled.lock().unwrap() = true;
+            self.stop.store(true, Ordering::Relaxed);
+        }
+
+        match thinking.thread.join() {
+            Ok((search, played)) => {
+                self.search = Some(search);
+                if let Some(mv) = played {
+                    self.play(mv);
+                }
+            }
+            Err(_) => {
+                eprintln!("Search thread panicked, starting over with an empty table");
+                let search = Search::new(DEFAULT_HASH_MB);
+                self.stop = search.stop_flag();
+                self.search = Some(search);
+            }
+        }
+    }
+}
+
+// "normal" and "fischerandom" are XBoard's names for standard chess, and
+// its "giveaway" is won by the side left without moves like Antichess
+fn xboard_name(variant: &Variant) -> String {
+    match variant.protocol_name().as_str() {
+        "chess" => String::from("normal"),
+        "antichess" => String::from("giveaway"),
+        name => String::from(name),
+    }
+}
+
+fn from_xboard_name(name: &str) -> Option<Variant> {
+    let standard = Variant::standard();
+
+    match name {
+        "normal" => Some(standard),
+        "fischerandom" => Some(Variant { chess960: true, ..standard }),
+        name => Variant::all().into_iter().find(|v| xboard_name(v) == name),
+    }
+}
+
+// Castling is sent as "O-O" and "O-O-O" in Chess960 and as the king's two
+// square step otherwise
+fn xboard_move(mv: Move, state: &BoardState) -> String {
+    match rules::castling_destination(mv, state) {
+        Some(_) if state.variant.chess960 => match mv.to.0 > mv.from.0 {
+            true => String::from("O-O"),
+            false => String::from("O-O-O"),
+        },
+        Some(to) => Move::new(mv.from, to).to_text(state.height),
+        None => mv.to_text(state.height),
+    }
+}
+
+// "O-O" or "O-O-O" (also with zeros) as the castling move of the side to move
+fn castling(text: &str, state: &BoardState) -> Option<Move> {
+    let king_side = match text.replace('0', "O").as_str() {
+        "O-O" => true,
+        "O-O-O" => false,
+        _ => return None,
+    };
+
+    rules::legal_moves(state)
+        .into_iter()
+        .find(|mv| rules::castling_destination(*mv, state).is_some() && (mv.to.0 > mv.from.0) == king_side)
+}
+
+// "1-0 {White wins}" and so on once the game is over
+fn game_result(state: &BoardState) -> Option<String> {
+    match rules::outcome(state)? {
+        Outcome::Win(Player::White) => Some(String::from("1-0 {White wins}")),
+        Outcome::Win(Player::Black) => Some(String::from("0-1 {Black wins}")),
+        Outcome::Draw => Some(String::from("1/2-1/2 {Draw}")),
+    }
+}
+
+// Thinking output: depth, score in centipawns, time in centiseconds, nodes
+// and the line
+fn report(info: &Info, state: &BoardState) {
+    let score = match info.score {
+        s if s >= MATE_BOUND => XBOARD_MATE + (MATE - s + 1) / 2,
+        s if s <= -MATE_BOUND => -XBOARD_MATE - (MATE + s) / 2,
+        s => s,
+    };
+
+    let mut pos = state.clone();
+    let pv: Vec<String> = info
+        .pv
+        .iter()
+        .map(|mv| {
+            let text = xboard_move(*mv, &pos);
+            pos.make_move(*mv);
+            text
+        })
+        .collect();
+
+    send(&format!("{} {} {} {} {}", info.depth, score, info.elapsed.as_millis() / 10, info.nodes, pv.join(" ")));
+}